@@ -1,2 +1,2 @@
+pub mod thing;
 pub mod vect3d;
-pub mod thing;
\ No newline at end of file
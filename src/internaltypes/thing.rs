@@ -1,24 +1,42 @@
-#[derive(Debug, Clone)]
+use crate::res::util::kinds::V2;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum PropertyKind {
     INT(i32),
     FLT(f32),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Attribute {
     PROPERTY(PropertyKind),
     SCRIPT,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Thing {
     name: String,
-    attributes: Vec<Attribute>
+    attributes: Vec<Attribute>,
+
+    pub pos: V2,
+    /// the sector `pos` is in, found when the level is loaded
+    pub sector: usize,
+    /// texture id of the billboard it is drawn as, 0 for none
+    pub sprite: usize,
+    /// world height of the billboard, standing on the sector floor; the
+    /// width follows from the sprite's proportions
+    pub height: f32,
 }
 
 impl Thing {
     pub fn new(name: String) -> Self {
-        Thing { name, attributes: vec![] }
+        Thing {
+            name,
+            attributes: vec![],
+            pos: V2::default(),
+            sector: 0,
+            sprite: 0,
+            height: 1.0,
+        }
     }
 
     pub fn get_name(self) -> String {
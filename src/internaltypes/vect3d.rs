@@ -1,5 +1,3 @@
-pub struct Vect3D {
-    
-}
+pub struct Vect3D {}
 
-pub type Vec3D = (i32, i32, i32);
\ No newline at end of file
+pub type Vec3D = (i32, i32, i32);
@@ -3,30 +3,303 @@ extern crate sdl2_sys;
 pub mod internaltypes;
 pub mod res;
 
-use crate::res::game::render;
-use crate::res::game::State;
-use crate::res::util::constants::*;
-use crate::res::util::kinds::*;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::res::game::{
+    load_sectors, move_camera, reload_sectors, render, update_camera_sector, update_camera_z, State,
+};
+use crate::res::level::binary::compile_level;
+use crate::res::level::portals::LinkMode;
+use crate::res::level::watch::LevelWatcher;
+use crate::res::present::sdl::SdlPresenter;
+use crate::res::present::Presenter;
+use crate::res::screenshot::save_screenshot;
+use crate::res::texture::Textures;
+use crate::res::util::kinds::{Fog, RenderConfig, V2};
+use crate::res::util::math::deg_2_rad;
 
 use sdl2_sys::*;
 
-fn main() {
-    lazy_static! {
-    static ref state: State = State {
-        
-    }
+const LEVEL_PATH: &str = "level.txt";
+
+/// Images in here are loaded as textures named after their files.
+const TEXTURE_DIR: &str = "textures";
+
+const WINDOW_WIDTH: i32 = 1280;
+const WINDOW_HEIGHT: i32 = 720;
+
+/// What F2 turns on.
+const FOG: Fog = Fog {
+    color: 0xFF302820,
+    start: 2.0,
+    density: 0.15,
+};
+
+const USAGE: &str = "usage: zengine [--size WIDTHxHEIGHT] [--fov DEGREES] [--threads N]
+       zengine --compile [--link] SRC DST";
+
+/// What the command line asks for.
+#[derive(Debug, PartialEq)]
+enum Command {
+    /// open the window and play `LEVEL_PATH`
+    Run(RenderConfig),
+    /// compile the level at `src` into the binary format at `dst` and exit,
+    /// first linking solid walls that have a twin into portals if `link`
+    Compile {
+        src: String,
+        dst: String,
+        link: bool,
+    },
+}
+
+/// The command asked for on the command line. Running renders with the
+/// defaults for whatever isn't given and a thread for every core unless told
+/// otherwise.
+fn parse_config(mut args: impl Iterator<Item = String>) -> Result<Command, String> {
+    let mut config = RenderConfig {
+        threads: std::thread::available_parallelism().map_or(1, |n| n.get()),
+        ..RenderConfig::default()
     };
+
+    while let Some(arg) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| format!("{} needs a value", arg))?;
+        match arg.as_str() {
+            "--compile" => {
+                let link = value == "--link";
+                let src = if link { args.next() } else { Some(value) };
+                let (Some(src), Some(dst)) = (src, args.next()) else {
+                    return Err("--compile needs a source and a destination".to_string());
+                };
+                if let Some(arg) = args.next() {
+                    return Err(format!("unexpected {} after --compile", arg));
+                }
+                return Ok(Command::Compile { src, dst, link });
+            }
+            "--size" => {
+                let (width, height) = value
+                    .split_once('x')
+                    .and_then(|(w, h)| Some((w.parse::<usize>().ok()?, h.parse::<usize>().ok()?)))
+                    .filter(|&(w, h)| w >= 2 && h >= 2 && h <= u16::MAX as usize)
+                    .ok_or_else(|| format!("bad size \"{}\"", value))?;
+                config.width = width;
+                config.height = height;
+            }
+            "--fov" => {
+                config.hfov = value
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|fov| (1.0..180.0).contains(fov))
+                    .map(deg_2_rad)
+                    .ok_or_else(|| format!("bad field of view \"{}\"", value))?;
+            }
+            "--threads" => {
+                config.threads = value
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|&n| n >= 1)
+                    .ok_or_else(|| format!("bad thread count \"{}\"", value))?;
+            }
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+
+    Ok(Command::Run(config))
 }
 
-    println!("Hello World!");
-    // game -> things -> attributes
-    let x = c();
+fn main() {
+    let command = parse_config(std::env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("{}\n{}", e, USAGE);
+        std::process::exit(1);
+    });
+
+    let config = match command {
+        Command::Run(config) => config,
+        Command::Compile { src, dst, link } => {
+            match compile_level(&src, &dst, link.then_some(LinkMode::Fill)) {
+                Ok(()) => {
+                    println!("compiled {} to {}", src, dst);
+                    return;
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+    };
+
+    let mut presenter = SdlPresenter::new(
+        "zengine",
+        WINDOW_WIDTH,
+        WINDOW_HEIGHT,
+        config.width,
+        config.height,
+    )
+    .unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+
+    let mut state = State::with_config(config);
+    state.textures = Textures::builtin();
+    if std::path::Path::new(TEXTURE_DIR).is_dir() {
+        match state.textures.load_dir(TEXTURE_DIR) {
+            Ok((n, errors)) => {
+                for e in errors {
+                    eprintln!("{}", e);
+                }
+                println!("loaded {} textures from {}", n, TEXTURE_DIR);
+            }
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+    state.camera.pos = V2::new(3.0, 3.0);
+
+    if let Err(e) = load_sectors(LEVEL_PATH, &mut state) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    println!(
+        "loaded {} sectors with {} walls",
+        state.sectors.len() - 1,
+        state.walls.len()
+    );
+
+    let mut watcher = LevelWatcher::new(LEVEL_PATH, Duration::from_millis(500));
+
+    while !state.quit {
+        let mut screenshot = false;
+        let mut ev = std::mem::MaybeUninit::<SDL_Event>::uninit();
+        while unsafe { SDL_PollEvent(ev.as_mut_ptr()) } != 0 {
+            let ev = unsafe { ev.assume_init_ref() };
+            let kind = unsafe { ev.type_ };
+            if kind == SDL_EventType::SDL_QUIT as u32 {
+                state.quit = true;
+            }
+
+            // on the press itself, not the key repeat
+            if kind == SDL_EventType::SDL_KEYDOWN as u32 {
+                let key = unsafe { ev.key };
+                match key.keysym.scancode {
+                    _ if key.repeat != 0 => {}
+                    SDL_Scancode::SDL_SCANCODE_F2 => {
+                        state.fog = match state.fog {
+                            Some(_) => Option::None,
+                            _ => Some(FOG),
+                        }
+                    }
+                    SDL_Scancode::SDL_SCANCODE_F12 => screenshot = true,
+                    _ => {}
+                }
+            }
+        }
+
+        if watcher.changed() {
+            match reload_sectors(LEVEL_PATH, &mut state) {
+                Ok(()) => println!("reloaded {}", LEVEL_PATH),
+                Err(e) => eprintln!("{}\nkeeping the previous level", e),
+            }
+        }
+
+        let keystate = unsafe {
+            let mut numkeys = 0;
+            let keys = SDL_GetKeyboardState(&mut numkeys);
+            std::slice::from_raw_parts(keys, numkeys as usize)
+        };
+        let key = |scancode: SDL_Scancode| keystate[scancode as usize] != 0;
+
+        let rot_speed = 3.0 * 0.016;
+        let move_speed = 3.0 * 0.016;
+
+        if key(SDL_Scancode::SDL_SCANCODE_RIGHT) {
+            state.camera.angle -= rot_speed;
+        }
+        if key(SDL_Scancode::SDL_SCANCODE_LEFT) {
+            state.camera.angle += rot_speed;
+        }
+        state.camera.anglecos = state.camera.angle.cos();
+        state.camera.anglesin = state.camera.angle.sin();
+
+        if key(SDL_Scancode::SDL_SCANCODE_UP) {
+            let step = V2::new(
+                move_speed * state.camera.anglecos,
+                move_speed * state.camera.anglesin,
+            );
+            move_camera(&mut state, step);
+        }
+        if key(SDL_Scancode::SDL_SCANCODE_DOWN) {
+            let step = V2::new(
+                -move_speed * state.camera.anglecos,
+                -move_speed * state.camera.anglesin,
+            );
+            move_camera(&mut state, step);
+        }
+        if key(SDL_Scancode::SDL_SCANCODE_F1) {
+            state.sleepy = true;
+        }
+
+        update_camera_sector(&mut state);
+        update_camera_z(&mut state);
+
+        state.framebuffer.clear(0);
+        render(&mut state, &mut presenter);
+        if !state.sleepy {
+            presenter.present(&state.framebuffer);
+        }
+
+        if screenshot {
+            let millis = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |t| t.as_millis());
+            let path = format!("screenshot-{}.png", millis);
+            match save_screenshot(&path, &state.framebuffer) {
+                Ok(()) => println!("saved {}", path),
+                Err(e) => eprintln!("error: {}: {}", path, e),
+            }
+        }
+    }
 }
 
-fn c() {
-    unsafe {
-        let init_res = SDL_Init(SDL_INIT_VIDEO);
-        assert!(init_res == 0, "SDL failed to initialize: ");
-        
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Command, String> {
+        parse_config(args.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn compile() {
+        assert_eq!(
+            parse("--compile level.txt level.zlb"),
+            Ok(Command::Compile {
+                src: "level.txt".to_string(),
+                dst: "level.zlb".to_string(),
+                link: false,
+            })
+        );
+        assert_eq!(
+            parse("--compile --link level.txt level.zlb"),
+            Ok(Command::Compile {
+                src: "level.txt".to_string(),
+                dst: "level.zlb".to_string(),
+                link: true,
+            })
+        );
+        assert!(parse("--compile level.txt").is_err());
+        assert!(parse("--compile --link level.txt").is_err());
+        assert!(parse("--compile a b c").is_err());
+    }
+
+    #[test]
+    fn run() {
+        match parse("--size 320x200 --threads 2") {
+            Ok(Command::Run(config)) => {
+                assert_eq!((config.width, config.height, config.threads), (320, 200, 2))
+            }
+            other => panic!("expected to run, got {:?}", other),
+        }
     }
 }
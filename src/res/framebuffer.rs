@@ -0,0 +1,154 @@
+/// Pixels a frame is drawn into, ABGR8888 with row 0 at the bottom of the
+/// screen. Nothing here knows about windows; a `Presenter` puts the finished
+/// frame somewhere.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Framebuffer {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u32>,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Framebuffer {
+            width,
+            height,
+            pixels: vec![0; width * height],
+        }
+    }
+
+    pub fn clear(&mut self, color: u32) {
+        self.pixels.fill(color);
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> u32 {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn set(&mut self, x: i32, y: i32, color: u32) {
+        self.pixels[y as usize * self.width + x as usize] = color;
+    }
+
+    /// Fills column `x` from `y0` to `y1` inclusive.
+    pub fn verline(&mut self, x: i32, y0: i32, y1: i32, color: u32) {
+        for y in y0..=y1 {
+            self.pixels[y as usize * self.width + x as usize] = color;
+        }
+    }
+
+    /// A copy of columns `x0..=x1`.
+    pub fn columns(&self, x0: usize, x1: usize) -> Self {
+        let width = x1 + 1 - x0;
+        let mut strip = Framebuffer::new(width, self.height);
+        for (dst, src) in strip
+            .pixels
+            .chunks_exact_mut(width)
+            .zip(self.pixels.chunks_exact(self.width))
+        {
+            dst.copy_from_slice(&src[x0..=x1]);
+        }
+        strip
+    }
+
+    /// Copies `strip` back in with its left edge at column `x0`.
+    pub fn paste(&mut self, x0: usize, strip: &Framebuffer) {
+        for (dst, src) in self
+            .pixels
+            .chunks_exact_mut(self.width)
+            .zip(strip.pixels.chunks_exact(strip.width))
+        {
+            dst[x0..x0 + strip.width].copy_from_slice(src);
+        }
+    }
+
+    /// Rows from the top of the screen down, the order images are stored in.
+    pub fn rows_top_down(&self) -> impl Iterator<Item = &[u32]> {
+        self.pixels.chunks_exact(self.width.max(1)).rev()
+    }
+}
+
+/// How far away what was drawn is, for passes after `render` to depth-test
+/// against. Depths are along the view direction, the same as the renderer's
+/// camera space y, and infinite where nothing was drawn or only sky.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DepthBuffer {
+    pub width: usize,
+    pub height: usize,
+    /// per column, the depth of the wall or steps that close it off; nothing
+    /// further away shows in that column
+    pub columns: Vec<f32>,
+    /// per pixel, if kept, laid out like `Framebuffer::pixels`
+    pub pixels: Option<Vec<f32>>,
+}
+
+impl DepthBuffer {
+    /// Per-column depths only, or per-pixel ones as well if `per_pixel`.
+    pub fn new(width: usize, height: usize, per_pixel: bool) -> Self {
+        DepthBuffer {
+            width,
+            height,
+            columns: vec![f32::INFINITY; width],
+            pixels: per_pixel.then(|| vec![f32::INFINITY; width * height]),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.columns.fill(f32::INFINITY);
+        if let Some(pixels) = &mut self.pixels {
+            pixels.fill(f32::INFINITY);
+        }
+    }
+
+    pub fn column(&self, x: usize) -> f32 {
+        self.columns[x]
+    }
+
+    /// Depth at a pixel, or of its column without per-pixel depths.
+    pub fn get(&self, x: usize, y: usize) -> f32 {
+        match &self.pixels {
+            Some(pixels) => pixels[y * self.width + x],
+            None => self.columns[x],
+        }
+    }
+
+    /// Whether something at `depth` would be in front of what is drawn at
+    /// the pixel.
+    pub fn test(&self, x: usize, y: usize, depth: f32) -> bool {
+        depth < self.get(x, y)
+    }
+
+    pub fn set(&mut self, x: i32, y: i32, depth: f32) {
+        if let Some(pixels) = &mut self.pixels {
+            pixels[y as usize * self.width + x as usize] = depth;
+        }
+    }
+
+    /// Records that nothing past `depth` shows in column `x`.
+    pub fn close_column(&mut self, x: i32, depth: f32) {
+        let column = &mut self.columns[x as usize];
+        *column = column.min(depth);
+    }
+
+    /// Copies `strip` in with its left edge at column `x0`, as
+    /// `Framebuffer::paste`.
+    pub fn paste(&mut self, x0: usize, strip: &DepthBuffer) {
+        self.columns[x0..x0 + strip.width].copy_from_slice(&strip.columns);
+        if let (Some(dst), Some(src)) = (&mut self.pixels, &strip.pixels) {
+            for (dst, src) in dst
+                .chunks_exact_mut(self.width)
+                .zip(src.chunks_exact(strip.width))
+            {
+                dst[x0..x0 + strip.width].copy_from_slice(src);
+            }
+        }
+    }
+
+    /// Sets column `x` from `y0` to `y1` inclusive, as `Framebuffer::verline`.
+    pub fn verline(&mut self, x: i32, y0: i32, y1: i32, depth: f32) {
+        if let Some(pixels) = &mut self.pixels {
+            for y in y0..=y1 {
+                pixels[y as usize * self.width + x as usize] = depth;
+            }
+        }
+    }
+}
@@ -1,168 +1,604 @@
-use std::fs::File;
-use std::io::{BufRead, BufReader};
-use std::slice;
+use super::framebuffer::{DepthBuffer, Framebuffer};
+use super::level::error::{LevelError, ReloadError};
+use super::level::validate::validate_level;
+use super::level::{read_level, Level, PlayerStart};
+use super::present::Presenter;
+use super::texture::{Image, Texture, Textures};
+use crate::internaltypes::thing::Thing;
 
 use super::util::constants::*;
-use super::util::kinds::{Sector, V2i, Wall, V2};
+use super::util::kinds::{Camera, Fog, RenderConfig, Sector, Sectors, V2i, Wall, Walls, V2};
 use super::util::math::*;
-use super::util::{
-    constants::SCREEN_WIDTH,
-    kinds::{Camera, Sectors, Walls},
-};
-
-use sdl2_sys::*;
 
 #[derive(Debug, Clone)]
 pub struct State {
-    pub window: *mut SDL_Window,
-    pub renderer: *mut SDL_Renderer,
-    pub texture: *mut SDL_Texture,
-    pub debug: *mut SDL_Texture,
-    pub pixels: Vec<u32>,
+    pub config: RenderConfig,
+    pub framebuffer: Framebuffer,
+    /// how far away what `render` drew is, column by column and, if kept,
+    /// pixel by pixel
+    pub depth: DepthBuffer,
     pub quit: bool,
 
     pub sectors: Sectors,
     pub walls: Walls,
+    pub things: Vec<Thing>,
+    pub textures: Textures,
+    /// kept from the loaded level so `level` can hand it back for saving
+    pub title: Option<String>,
+    pub player: Option<PlayerStart>,
 
-    pub y_lo: [u16; SCREEN_WIDTH],
-    pub y_hi: [u16; SCREEN_WIDTH],
+    /// per column, the rows still open while rendering
+    pub y_lo: Vec<u16>,
+    pub y_hi: Vec<u16>,
 
     pub camera: Camera,
+    pub fog: Option<Fog>,
 
     pub sleepy: bool,
 }
 
-pub enum ScanState {
-    ScanSector,
-    ScanWall,
-    ScanNone,
+impl State {
+    /// An empty level with the camera at the origin, looking down +X.
+    pub fn new() -> Self {
+        Self::with_config(RenderConfig::default())
+    }
+
+    /// As `new`, rendering as `config` says.
+    pub fn with_config(config: RenderConfig) -> Self {
+        State {
+            config,
+            framebuffer: Framebuffer::new(config.width, config.height),
+            depth: DepthBuffer::new(config.width, config.height, false),
+            quit: false,
+            sectors: Sectors::new(),
+            walls: Walls::new(),
+            things: vec![],
+            textures: Textures::new(),
+            title: None,
+            player: None,
+            y_lo: vec![0; config.width],
+            y_hi: vec![0; config.width],
+            camera: Camera {
+                pos: V2::new(0.0, 0.0),
+                z: EYE_Z,
+                angle: 0.0,
+                anglecos: 1.0,
+                anglesin: 0.0,
+                sector: 1,
+                reflections: 0,
+            },
+            fog: None,
+            sleepy: false,
+        }
+    }
+
+    /// Renders as `config` says from the next frame on, resizing the
+    /// framebuffer and everything kept per column. Whether the depth buffer
+    /// keeps per-pixel depths stays as it was.
+    pub fn set_config(&mut self, config: RenderConfig) {
+        self.config = config;
+        self.framebuffer = Framebuffer::new(config.width, config.height);
+        let per_pixel = self.depth.pixels.is_some();
+        self.depth = DepthBuffer::new(config.width, config.height, per_pixel);
+        self.y_lo = vec![0; config.width];
+        self.y_hi = vec![0; config.width];
+    }
+
+    /// The level as it is now, edits and all, with texture references
+    /// named by `textures` so it can be saved.
+    pub fn level(&self) -> Level {
+        Level {
+            sectors: self.sectors.clone(),
+            walls: self.walls.clone(),
+            title: self.title.clone(),
+            player: self.player.clone(),
+            things: self.things.clone(),
+            textures: self.textures.names().to_vec(),
+        }
+    }
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-fn load_sectors(path: &str, state: &mut State) -> Result<(), i32> {
-    // sector 0 does not exist
-    state.sectors.n = 1;
+/// Points a level's textures at `textures`. Textures that aren't loaded are
+/// reported and whatever uses them drawn flat.
+fn bind_textures(path: &str, level: &mut Level, textures: &Textures) {
+    for name in textures.bind(level) {
+        eprintln!("warning: {}: texture \"{}\" is not loaded", path, name);
+    }
+}
 
-    let f = match File::open(path) {
-        Ok(file) => file,
-        Err(_) => return Err(-1),
+/// Loads a level into `state` and puts the camera at its player start, if it
+/// has one. Nothing in `state` changes unless the level loads and validates.
+pub fn load_sectors(path: &str, state: &mut State) -> Result<(), ReloadError> {
+    let level = prepare_level(path, &state.textures)?;
+
+    // without a player start the camera stays wherever it was put
+    let start = match &level.player {
+        Some(player) => Some((
+            player.clone(),
+            player_sector(path, player, &level).map_err(ReloadError::Load)?,
+        )),
+        None => None,
     };
-    let reader = BufReader::new(f);
 
-    let mut retval = 0;
-    let mut ss = ScanState::ScanNone;
+    state.sectors = level.sectors;
+    state.walls = level.walls;
+    state.things = level.things;
+    state.title = level.title;
+    state.player = level.player;
+
+    if let Some((player, sector)) = start {
+        state.camera.pos = player.pos;
+        state.camera.angle = player.angle;
+        state.camera.anglecos = player.angle.cos();
+        state.camera.anglesin = player.angle.sin();
+        state.camera.sector = sector as i32;
+        update_camera_z(state);
+    }
+
+    Ok(())
+}
 
-    for line in reader.lines() {
-        let line = match line {
-            Ok(l) => l,
-            Err(_) => {
-                retval = -128;
-                break;
+/// The sector `player` starts in, as the level gives it or else found from
+/// where the player stands.
+fn player_sector(path: &str, player: &PlayerStart, level: &Level) -> Result<usize, LevelError> {
+    match player.sector {
+        Some(sector) if (1..level.sectors.len()).contains(&sector) => Ok(sector),
+        Some(sector) => Err(LevelError::BadReference {
+            path: path.to_string(),
+            message: format!("player starts in sector {} which does not exist", sector),
+        }),
+        None => find_sector(&level.sectors, &level.walls, &player.pos).ok_or_else(|| {
+            LevelError::BadReference {
+                path: path.to_string(),
+                message: format!(
+                    "player start ({}, {}) is not inside any sector",
+                    player.pos.x, player.pos.y
+                ),
             }
-        };
+        }),
+    }
+}
+
+/// Reads and validates a level, binds it to `textures` and places its
+/// things, leaving it ready to be moved into a state. Shared by loading and
+/// reloading so both reject the same levels.
+fn prepare_level(path: &str, textures: &Textures) -> Result<Level, ReloadError> {
+    let mut level = read_level(path).map_err(ReloadError::Load)?;
+
+    let problems = validate_level(&level.sectors, &level.walls);
+    if !problems.is_empty() {
+        return Err(ReloadError::Invalid(problems));
+    }
+    bind_textures(path, &mut level, textures);
+    place_things(path, &level.sectors, &level.walls, &mut level.things)
+        .map_err(ReloadError::Load)?;
+
+    Ok(level)
+}
+
+/// Swaps a freshly loaded level into `state`, leaving the camera where it
+/// is. If the new level fails to load or validate the current one is kept.
+pub fn reload_sectors(path: &str, state: &mut State) -> Result<(), ReloadError> {
+    let level = prepare_level(path, &state.textures)?;
 
-        let mut p = line.trim_start();
-        if p.is_empty() || p.starts_with('#') {
+    // sector indices may mean something else in the new level
+    let sector = match find_sector(&level.sectors, &level.walls, &state.camera.pos) {
+        Some(sector) => sector as i32,
+        _ if (state.camera.sector as usize) < level.sectors.len() => state.camera.sector,
+        _ => return Err(ReloadError::CameraLost),
+    };
+
+    state.sectors = level.sectors;
+    state.walls = level.walls;
+    state.things = level.things;
+    state.title = level.title;
+    state.player = level.player;
+    state.camera.sector = sector;
+
+    Ok(())
+}
+
+/// Finds the sector of `sectors` each thing stands in.
+fn place_things(
+    path: &str,
+    sectors: &Sectors,
+    walls: &Walls,
+    things: &mut [Thing],
+) -> Result<(), LevelError> {
+    for thing in things {
+        thing.sector =
+            find_sector(sectors, walls, &thing.pos).ok_or_else(|| LevelError::BadReference {
+                path: path.to_string(),
+                message: format!(
+                    "thing \"{}\" at ({}, {}) is not inside any sector",
+                    thing.clone().get_name(),
+                    thing.pos.x,
+                    thing.pos.y
+                ),
+            })?;
+    }
+
+    Ok(())
+}
+
+/// Finds the sector containing `p`, if any.
+pub fn locate_sector(p: &V2, state: &mut State) -> Option<usize> {
+    find_sector(&state.sectors, &state.walls, p)
+}
+
+/// Finds the sector of `sectors` containing `p`, if any.
+fn find_sector(sectors: &Sectors, walls: &Walls, p: &V2) -> Option<usize> {
+    (1..sectors.len()).find(|&i| sector_contains(&sectors.arr[i], walls, p))
+}
+
+/// Stands the eye `EYE_Z` above the floor under the camera, wherever it is
+/// on a slope.
+pub fn update_camera_z(state: &mut State) {
+    let sector = &state.sectors.arr[state.camera.sector as usize];
+    state.camera.z = sector.floor_at(&state.walls, &state.camera.pos) + EYE_Z;
+}
+
+/// Moves the camera by `step`. Stepping through a transforming portal
+/// carries it, facing the same way relative to the portal, into the sector
+/// behind, wherever that is in the map; everything else is left to
+/// `update_camera_sector`. Solid walls, steps up higher than `STEP_HEIGHT`
+/// and ceilings less than `HEADROOM` above the eye stop the camera, which
+/// slides along a solid wall instead of stopping dead.
+pub fn move_camera(state: &mut State, step: V2) {
+    if let Err(wall) = try_move_camera(state, &step) {
+        // what is left of the step along the wall, if that gets anywhere
+        let (a, b) = (wall.a.v2i_to_v2(), wall.b.v2i_to_v2());
+        let along = V2::new(b.x - a.x, b.y - a.y);
+        let t = (step.x * along.x + step.y * along.y) / (along.x * along.x + along.y * along.y);
+        let _ = try_move_camera(state, &V2::new(along.x * t, along.y * t));
+    }
+}
+
+/// Moves the camera by `step`, or leaves it where it is and returns the
+/// wall in the way.
+fn try_move_camera(state: &mut State, step: &V2) -> Result<(), Wall> {
+    let from = state.camera.pos.clone();
+    let to = V2::new(from.x + step.x, from.y + step.y);
+    let sector = &state.sectors.arr[state.camera.sector as usize];
+    let floor = sector.floor_at(&state.walls, &from);
+
+    for wall in &state.walls.arr[sector.firstwall..sector.firstwall + sector.nwalls] {
+        let (a, b) = (wall.a.clone().v2i_to_v2(), wall.b.clone().v2i_to_v2());
+
+        // only on the way out, and through the wall rather than past its end
+        if point_side(to.clone(), a.clone(), b.clone()) <= 0.0
+            || intersect_segs(&from, &to, &a, &b).x.is_nan()
+        {
             continue;
-        } else if p.starts_with('[') {
-            p = p.trim_start_matches(|c| c == '[');
-            let buf = [0; 64];
-            if let Some(section) = p.split(']').next() {
-                match section {
-                    "SECTOR" => ss = ScanState::ScanSector,
-                    "WALL" => ss = ScanState::ScanWall,
-                    _ => {
-                        retval = -3;
-                        break;
-                    }
-                }
-            } else {
-                retval = -2;
-                break;
-            }
-        } else {
-            match ss {
-                ScanState::ScanWall => {
-                    if let Some(wall) = state.walls.arr.get_mut(state.walls.n) {
-                        let parts: Vec<&str> = p.split_whitespace().collect();
-                        if parts.len() != 5 {
-                            retval = -4;
-                            break;
-                        }
-                        if let (Ok(ax), Ok(ay), Ok(bx), Ok(by), Ok(portal)) = (
-                            parts[0].parse::<i32>(),
-                            parts[1].parse::<i32>(),
-                            parts[2].parse::<i32>(),
-                            parts[3].parse::<i32>(),
-                            parts[4].parse::<i32>(),
-                        ) {
-                            wall.a.x = ax;
-                            wall.a.y = ay;
-                            wall.b.x = bx;
-                            wall.b.y = by;
-                            wall.portal = portal as usize;
-                            state.walls.n += 1;
-                        } else {
-                            retval = -4;
-                            break;
-                        }
-                    } else {
-                        retval = -4;
-                        break;
-                    }
-                }
-                ScanState::ScanSector => {
-                    if let Some(sector) = state.sectors.arr.get_mut(state.sectors.n) {
-                        let parts: Vec<&str> = p.split_whitespace().collect();
-                        if parts.len() != 5 {
-                            retval = -5;
-                            break;
-                        }
-                        if let (Ok(id), Ok(firstwall), Ok(nwalls), Ok(zfloor), Ok(zceil)) = (
-                            parts[0].parse::<i32>(),
-                            parts[1].parse::<usize>(),
-                            parts[2].parse::<usize>(),
-                            parts[3].parse::<f32>(),
-                            parts[4].parse::<f32>(),
-                        ) {
-                            sector.id = id;
-                            sector.firstwall = firstwall;
-                            sector.nwalls = nwalls;
-                            sector.zfloor = zfloor;
-                            sector.zceil = zceil;
-                            state.sectors.n += 1;
-                        } else {
-                            retval = -5;
-                            break;
-                        }
-                    } else {
-                        retval = -5;
-                        break;
-                    }
-                }
-                ScanState::ScanNone => {
-                    retval = -6;
-                    break;
-                }
-            }
         }
-    }
 
-    if retval != 0 {
-        return Err(retval);
+        let behind = match &wall.transform {
+            Some(transform) => transform.apply(&to),
+            None => to.clone(),
+        };
+        if wall.portal as i32 == SECTOR_NON
+            || !can_stand(
+                &state.sectors.arr[wall.portal],
+                &state.walls,
+                &behind,
+                floor,
+            )
+        {
+            return Err(wall.clone());
+        }
+
+        if let Some(transform) = &wall.transform {
+            let moved = Camera {
+                pos: to,
+                ..state.camera.clone()
+            };
+            state.camera = Camera {
+                sector: wall.portal as i32,
+                ..transform.apply_camera(&moved)
+            };
+            return Ok(());
+        }
     }
 
+    state.camera.pos = to;
     Ok(())
 }
 
-pub fn verline(x: i32, y0: i32, y1: i32, color: u32, state: &mut State) {
+/// Whether the camera, standing on `floor`, can step to `p` in `sector`:
+/// up no more than `STEP_HEIGHT`, with `HEADROOM` left above the eye.
+fn can_stand(sector: &Sector, walls: &Walls, p: &V2, floor: f32) -> bool {
+    let (zfloor, zceil) = (sector.floor_at(walls, p), sector.ceil_at(walls, p));
+    zfloor - floor <= STEP_HEIGHT && zceil - zfloor >= EYE_Z + HEADROOM
+}
+
+/// Keeps `camera.sector` up to date after the camera moves, looking at the
+/// current sector and its neighbours before searching the whole level.
+/// Transforming portals are left to `move_camera`, since what is behind them
+/// is somewhere else.
+pub fn update_camera_sector(state: &mut State) {
+    let current = state.camera.sector as usize;
+    let pos = state.camera.pos.clone();
+    let sector = state.sectors.arr[current].clone();
+
+    if point_in_sector(&sector, pos.clone(), state) {
+        return;
+    }
+
+    let neighbours: Vec<usize> = state.walls.arr
+        [sector.firstwall..sector.firstwall + sector.nwalls]
+        .iter()
+        .filter(|wall| wall.transform.is_none())
+        .map(|wall| wall.portal)
+        .filter(|&portal| portal as i32 != SECTOR_NON)
+        .collect();
+
+    for portal in neighbours {
+        let next = state.sectors.arr[portal].clone();
+        if point_in_sector(&next, pos.clone(), state) {
+            state.camera.sector = portal as i32;
+            return;
+        }
+    }
+
+    if let Some(found) = locate_sector(&pos, state) {
+        state.camera.sector = found as i32;
+    }
+}
+
+pub fn verline(x: i32, y0: i32, y1: i32, color: u32, framebuffer: &mut Framebuffer) {
+    framebuffer.verline(x, y0, y1, color);
+}
+
+/// How the colours of a surface seen at one depth are lit and fogged.
+#[derive(Debug, Clone, Copy)]
+struct Shading {
+    /// out of 256, for `abgr_mul`
+    shade: u32,
+    /// how far to blend towards `fog_color`, out of 256
+    fog: u32,
+    fog_color: u32,
+    /// how far to blend towards `MIRROR_TINT`, out of 256
+    tint: u32,
+}
+
+/// How far whatever is seen through `reflections` mirrors is blended towards
+/// `MIRROR_TINT`, out of 256.
+fn mirror_tint(reflections: usize) -> u32 {
+    let kept = 1.0 - MIRROR_TINT_AMOUNT as f32 / 256.0;
+    (256.0 * (1.0 - kept.powi(reflections as i32))) as u32
+}
+
+fn apply_tint(color: u32, tint: u32) -> u32 {
+    match tint {
+        0 => color,
+        tint => abgr_mix(color, MIRROR_TINT, tint),
+    }
+}
+
+impl Shading {
+    /// Something lit at `light` seen `depth` away, light fading with depth
+    /// and the fog, if any, thickening towards the far clip at `zfar`.
+    fn new(light: f32, depth: f32, fog: Option<&Fog>, zfar: f32) -> Self {
+        let lit = light / (1.0 + depth * LIGHT_FALLOFF);
+        let (fog, fog_color) = match fog {
+            Some(fog) if depth >= zfar => (1.0, fog.color),
+            Some(fog) => (
+                1.0 - f32::exp(-fog.density * f32::max(depth - fog.start, 0.0)),
+                fog.color,
+            ),
+            None => (0.0, 0),
+        };
+
+        Shading {
+            shade: (256.0 * clamp(lit, 0.0, 1.0)) as u32,
+            fog: (256.0 * fog) as u32,
+            fog_color,
+            tint: 0,
+        }
+    }
+
+    /// The same, seen through `reflections` mirrors.
+    fn tinted(self, reflections: usize) -> Self {
+        Shading {
+            tint: mirror_tint(reflections),
+            ..self
+        }
+    }
+
+    fn apply(&self, color: u32) -> u32 {
+        let color = abgr_mul(color, self.shade);
+        let color = match self.fog {
+            0 => color,
+            fog => abgr_mix(color, self.fog_color, fog),
+        };
+        apply_tint(color, self.tint)
+    }
+}
+
+/// Where one screen column of a wall samples its texture.
+#[derive(Debug, Clone, Copy)]
+struct WallColumn {
+    /// horizontal texture coordinate
+    u: f32,
+    /// world units one pixel covers, along the wall and up it
+    du: f32,
+    dz: f32,
+    /// screen row `y_ref` shows world height `z_ref`
+    y_ref: i32,
+    z_ref: f32,
+    /// world height the texture hangs from
+    z_top: f32,
+    shading: Shading,
+}
+
+impl WallColumn {
+    /// Vertical texture coordinate at screen row `y`.
+    fn v(&self, y: i32) -> f32 {
+        self.z_top - (self.z_ref + (y - self.y_ref) as f32 * self.dz)
+    }
+}
+
+/// Fills column `x` from `y0` to `y1` from `image` as `column` says.
+fn texline(
+    x: i32,
+    y0: i32,
+    y1: i32,
+    image: &Image,
+    column: &WallColumn,
+    framebuffer: &mut Framebuffer,
+) {
+    let u = image.column(column.u);
+    for y in y0..=y1 {
+        framebuffer.set(x, y, column.shading.apply(image.texel(u, column.v(y))));
+    }
+}
+
+/// Draws part of a wall column, textured if it has a texture and in the flat
+/// `color` otherwise.
+fn wall_line(
+    x: i32,
+    y0: i32,
+    y1: i32,
+    texture: Option<&Texture>,
+    color: u32,
+    column: &WallColumn,
+    framebuffer: &mut Framebuffer,
+) {
+    match texture {
+        Some(texture) => {
+            // pick the mip level from how many texels a pixel spans
+            let texels = f32::max(
+                column.du * texture.width() as f32,
+                column.dz.abs() * texture.height() as f32,
+            );
+            texline(x, y0, y1, texture.mip(texels), column, framebuffer)
+        }
+        _ => verline(x, y0, y1, column.shading.apply(color), framebuffer),
+    }
+}
+
+/// A floor or ceiling at world height `z` straight above or below the
+/// camera, rising by `gradient` per unit moved along x and y, drawn from
+/// `texture` or in the flat `color` without one, lit at `light` and seen
+/// through `fog`.
+#[derive(Debug, Clone)]
+struct Plane<'a> {
+    z: f32,
+    gradient: V2,
+    texture: Option<&'a Texture>,
+    color: u32,
+    light: f32,
+    fog: Option<&'a Fog>,
+}
+
+/// Which way one screen column looks.
+#[derive(Debug, Clone, Copy)]
+struct ColumnRay<'a> {
+    camera: &'a Camera,
+    config: &'a RenderConfig,
+    /// the direction it looks in the world, for the sky
+    angle: f32,
+    /// how far the column leans sideways per unit of depth, and how much
+    /// that changes to the next column
+    dir: f32,
+    ddir: f32,
+}
+
+/// Draws part of a floor or ceiling column, finding the world position of
+/// every pixel from its depth so textures stay perspective correct and
+/// shading follows the distance.
+fn plane_line(
+    x: i32,
+    y0: i32,
+    y1: i32,
+    plane: &Plane,
+    ray: &ColumnRay,
+    framebuffer: &mut Framebuffer,
+    depth_buffer: &mut DepthBuffer,
+) {
+    let config = ray.config;
+    let camera = ray.camera;
+    let scale = config.vfov * config.height as f32;
+    let height = plane.z - camera.z;
+
+    // how much a sloped plane rises per unit of depth along this column
+    let ahead = camera_pos_to_world(V2::new(ray.dir, 1.0), camera);
+    let rise =
+        plane.gradient.x * (ahead.x - camera.pos.x) + plane.gradient.y * (ahead.y - camera.pos.y);
+
     for y in y0..=y1 {
-        state.pixels[(y * (SCREEN_WIDTH as i32) + x) as usize] = color;
+        // the depth at which this row meets the plane, from the projection
+        // the walls use
+        let dy = (y - config.height as i32 / 2) as f32 + 0.5;
+        let depth = height * scale / (dy - rise * scale);
+        if depth <= 0.0 {
+            // the plane faces away, as a floor above the eye does
+            let shading =
+                Shading::new(plane.light, 0.0, None, config.zfar).tinted(camera.reflections);
+            framebuffer.set(x, y, shading.apply(plane.color));
+            continue;
+        }
+
+        depth_buffer.set(x, y, depth);
+        let shading =
+            Shading::new(plane.light, depth, plane.fog, config.zfar).tinted(camera.reflections);
+        let Some(texture) = plane.texture else {
+            framebuffer.set(x, y, shading.apply(plane.color));
+            continue;
+        };
+
+        // world units one pixel covers, across and down the screen
+        let texels = f32::max(depth * ray.ddir.abs(), depth / dy.abs()) * texture.width() as f32;
+        let image = texture.mip(texels);
+
+        let p = camera_pos_to_world(V2::new(ray.dir * depth, depth), ray.camera);
+        framebuffer.set(x, y, shading.apply(image.texel(image.column(p.x), p.y)));
+    }
+}
+
+/// Draws part of a column open to the sky. The panorama goes once around
+/// the horizon, scrolling as the view turns, and hangs from the top of the
+/// screen down to the horizon. Without one the sky is a flat colour.
+fn sky_line(
+    x: i32,
+    y0: i32,
+    y1: i32,
+    sky: Option<&Texture>,
+    ray: &ColumnRay,
+    framebuffer: &mut Framebuffer,
+) {
+    let tint = mirror_tint(ray.camera.reflections);
+    let Some(sky) = sky else {
+        verline(x, y0, y1, apply_tint(0xFFE0A060, tint), framebuffer);
+        return;
+    };
+
+    // angles grow to the left, the panorama runs left to right
+    let image = &sky.mips[0];
+    let u = image.column(-ray.angle / TAU);
+    for y in y0..=y1 {
+        let height = ray.config.height as i32;
+        let v = (height - 1 - y) as f32 / (height / 2) as f32;
+        let texel = image.texel(u, clamp(v, 0.0, 0.999));
+        framebuffer.set(x, y, apply_tint(texel, tint));
     }
 }
 
 pub fn point_in_sector(sector: &Sector, p: V2, state: &mut State) -> bool {
+    sector_contains(sector, &state.walls, &p)
+}
+
+/// Whether `p` is on the inner side of every wall of `sector`, which for the
+/// convex sectors the renderer takes means inside it.
+fn sector_contains(sector: &Sector, walls: &Walls, p: &V2) -> bool {
     for i in 0..sector.nwalls {
-        let wall: &Wall = &state.walls.arr[sector.firstwall + i];
+        let wall: &Wall = &walls.arr[sector.firstwall + i];
 
         if point_side(
             p.clone(),
@@ -177,85 +613,356 @@ pub fn point_in_sector(sector: &Sector, p: V2, state: &mut State) -> bool {
     true
 }
 
-pub fn render(state: &mut State) {
-    for i in 0..SCREEN_WIDTH {
-        state.y_hi[i] = (SCREEN_HEIGHT - 1) as u16;
-        state.y_lo[i] = 0;
+/// The part of the screen a sector was seen through by `camera`: columns
+/// `x0..=x1`, each open from `y_lo` to `y_hi`, stored from `x0` on.
+#[derive(Debug, Clone)]
+struct SectorWindow {
+    sector: usize,
+    camera: Camera,
+    x0: i32,
+    x1: i32,
+    y_lo: Vec<u16>,
+    y_hi: Vec<u16>,
+}
+
+/// Draws the things in the sectors that were seen as billboards facing the
+/// camera, furthest first, each clipped to the window its sector was seen
+/// through so walls and steps in front of it hide it. A sector seen by more
+/// than one camera, through transforming portals or in mirrors, has its
+/// things drawn in every window.
+fn draw_things(view: &View, strip: &mut Strip, windows: &[SectorWindow]) {
+    let config = view.config;
+    let height = config.height as i32;
+    let dirs = &view.dirs;
+    let mut visible: Vec<(V2, &Thing, &SectorWindow)> = view
+        .things
+        .iter()
+        .flat_map(|thing| {
+            windows
+                .iter()
+                .filter(move |window| window.sector == thing.sector)
+                .map(move |window| {
+                    let cp = world_pos_to_camera(thing.pos.clone(), &window.camera);
+                    (cp, thing, window)
+                })
+        })
+        .filter(|(cp, _, _)| cp.y >= config.znear)
+        .collect();
+    visible.sort_by(|(a, _, _), (b, _, _)| b.y.total_cmp(&a.y));
+
+    for (cp, thing, window) in visible {
+        let Some(texture) = view.textures.get(thing.sprite) else {
+            continue;
+        };
+        let camera = &window.camera;
+        let sector = &view.sectors.arr[thing.sector];
+
+        let width = thing.height * texture.width() as f32 / texture.height() as f32;
+        let left = cp.x - width / 2.0;
+        let z_bottom = sector.floor_at(view.walls, &thing.pos);
+        let z_top = z_bottom + thing.height;
+
+        let sy = (config.vfov * height as f32) / cp.y;
+        let y_bottom = (height / 2) + ((z_bottom - camera.z) * sy) as i32;
+        let y_top = (height / 2) + ((z_top - camera.z) * sy) as i32;
+
+        // the mip level from how many texels a pixel spans at the centre
+        let ddir = dirs[config.width / 2 + 1] - dirs[config.width / 2];
+        let texels = f32::max(
+            ddir * cp.y / width * texture.width() as f32,
+            1.0 / sy / thing.height * texture.height() as f32,
+        );
+        let image = texture.mip(texels);
+        let shading =
+            Shading::new(sector.light, cp.y, view.fog, config.zfar).tinted(camera.reflections);
+
+        for x in window.x0..=window.x1 {
+            // sprites come out flipped in a mirror too
+            let u = (dirs[x as usize] * cp.y - left) / width;
+            let u = if camera.mirrored() { 1.0 - u } else { u };
+            if !(0.0..1.0).contains(&u) {
+                continue;
+            }
+
+            let i = (x - window.x0) as usize;
+            let y0 = max(y_bottom, window.y_lo[i] as i32);
+            let y1 = min(y_top, window.y_hi[i] as i32);
+            let column = image.column(u);
+            let sx = x - strip.x0;
+
+            for y in y0..=y1 {
+                let z = camera.z + ((y - height / 2) as f32 + 0.5) / sy;
+                let texel = image.texel(column, clamp((z_top - z) / thing.height, 0.0, 0.999));
+
+                // mostly transparent texels are holes
+                if texel >> 24 >= 0x80 {
+                    strip.framebuffer.set(sx, y, shading.apply(texel));
+                    strip.depth.set(sx, y, cp.y);
+                }
+            }
+        }
+    }
+}
+
+/// Everything a frame is drawn from, shared by all the strips it is drawn in.
+struct View<'a> {
+    config: RenderConfig,
+    sectors: &'a Sectors,
+    walls: &'a Walls,
+    things: &'a [Thing],
+    textures: &'a Textures,
+    camera: &'a Camera,
+    fog: Option<&'a Fog>,
+    sky: Option<&'a Texture>,
+    /// the view angle of each column, for the sky, and how far it leans
+    /// sideways per unit of depth, for floors and ceilings, with one extra to
+    /// take differences against
+    angles: Vec<f32>,
+    dirs: Vec<f32>,
+    /// sectors with things in them, whose windows are kept to draw the
+    /// things after the walls
+    occupied: Vec<bool>,
+}
+
+/// Columns `x0..=x1` of a frame and what is kept for them while drawing,
+/// all indexed from `x0`.
+struct Strip<'a> {
+    x0: i32,
+    x1: i32,
+    y_lo: &'a mut [u16],
+    y_hi: &'a mut [u16],
+    framebuffer: &'a mut Framebuffer,
+    depth: &'a mut DepthBuffer,
+}
+
+/// Draws the view from `state.camera` into `state.framebuffer`, in
+/// `config.threads` strips side by side on their own threads if more than
+/// one. The presenter is only used by the F1 debug mode, which shows every
+/// column as it is drawn and so always draws on this thread.
+pub fn render(state: &mut State, presenter: &mut dyn Presenter) {
+    let config = state.config;
+    let (width, height) = (config.width, config.height as i32);
+
+    state.depth.clear();
+    state.y_lo.fill(0);
+    state.y_hi.fill((height - 1) as u16);
+
+    let angles: Vec<f32> = (0..=width)
+        .map(|x| screen_x_to_angle(x as f32 + 0.5, &config))
+        .collect();
+    let dirs: Vec<f32> = angles.iter().map(|&a| -f32::tan(a)).collect();
+    let mut occupied = vec![false; state.sectors.len()];
+    for thing in &state.things {
+        occupied[thing.sector] |= thing.sprite != TEXTURE_NON;
     }
 
-    let mut sectdraw = [false; SECTOR_MAX as usize];
+    let view = View {
+        config,
+        sectors: &state.sectors,
+        walls: &state.walls,
+        things: &state.things,
+        textures: &state.textures,
+        camera: &state.camera,
+        fog: state.fog.as_ref(),
+        sky: state
+            .textures
+            .id(SKY_TEXTURE)
+            .and_then(|id| state.textures.get(id)),
+        angles,
+        dirs,
+        occupied,
+    };
+
+    let threads = clamp(config.threads, 1, width);
+    if threads == 1 || state.sleepy {
+        let mut strip = Strip {
+            x0: 0,
+            x1: (width - 1) as i32,
+            y_lo: &mut state.y_lo,
+            y_hi: &mut state.y_hi,
+            framebuffer: &mut state.framebuffer,
+            depth: &mut state.depth,
+        };
+        let presenter = if state.sleepy { Some(presenter) } else { None };
+        render_strip(&view, &mut strip, presenter);
+    } else {
+        // each strip draws into its own copy of its columns, which start out
+        // as whatever was in the framebuffer, so the pixels nothing covers
+        // come out the same as drawing it in one go
+        let per_pixel = state.depth.pixels.is_some();
+        let mut strips: Vec<(usize, usize, Framebuffer, DepthBuffer)> = (0..threads)
+            .map(|i| {
+                let (x0, x1) = (width * i / threads, width * (i + 1) / threads - 1);
+                let framebuffer = state.framebuffer.columns(x0, x1);
+                let depth = DepthBuffer::new(x1 + 1 - x0, height as usize, per_pixel);
+                (x0, x1, framebuffer, depth)
+            })
+            .collect();
+
+        std::thread::scope(|scope| {
+            let (mut y_lo, mut y_hi) = (&mut state.y_lo[..], &mut state.y_hi[..]);
+            for (x0, x1, framebuffer, depth) in &mut strips {
+                let (lo, rest_lo) = std::mem::take(&mut y_lo).split_at_mut(*x1 + 1 - *x0);
+                let (hi, rest_hi) = std::mem::take(&mut y_hi).split_at_mut(*x1 + 1 - *x0);
+                (y_lo, y_hi) = (rest_lo, rest_hi);
+
+                let mut strip = Strip {
+                    x0: *x0 as i32,
+                    x1: *x1 as i32,
+                    y_lo: lo,
+                    y_hi: hi,
+                    framebuffer,
+                    depth,
+                };
+                let view = &view;
+                scope.spawn(move || render_strip(view, &mut strip, None));
+            }
+        });
+
+        for (x0, _, framebuffer, depth) in &strips {
+            state.framebuffer.paste(*x0, framebuffer);
+            state.depth.paste(*x0, depth);
+        }
+    }
+
+    state.sleepy = false;
+}
+
+/// Draws the columns of `strip`. Every strip walks all the portals in the
+/// same order, drawing only its own columns, so each sector is claimed by
+/// the same portal in every strip and they fit together into the frame a
+/// single strip would draw. With a presenter the frame is shown after every
+/// column.
+fn render_strip(view: &View, strip: &mut Strip, mut presenter: Option<&mut dyn Presenter>) {
+    let config = view.config;
+    let (width, height) = (config.width, config.height as i32);
+
+    // every camera the frame is seen by, along with how many transforming
+    // portals it looks through and which sectors it has drawn: the view's
+    // own, then one more for each transforming portal or mirror
+    let mut cameras = vec![(view.camera.clone(), 0)];
+    let mut sectdraw = vec![vec![false; view.sectors.len()]];
+    let mut windows: Vec<SectorWindow> = vec![];
 
     // calculate edges of near/far planes (looking down +Y axis)
-    let zdl = rotate(V2 { x: 0.0, y: 1.0 }, HFOV / 2.0);
-    let zdr = rotate(V2 { x: 0.0, y: 1.0 }, -HFOV / 2.0);
+    let zdl = rotate(V2 { x: 0.0, y: 1.0 }, config.hfov / 2.0);
+    let zdr = rotate(V2 { x: 0.0, y: 1.0 }, -config.hfov / 2.0);
     let znl = V2 {
-        x: zdl.x * ZNEAR,
-        y: zdl.y * ZNEAR,
+        x: zdl.x * config.znear,
+        y: zdl.y * config.znear,
     };
     let znr = V2 {
-        x: zdr.x * ZNEAR,
-        y: zdr.y * ZNEAR,
+        x: zdr.x * config.znear,
+        y: zdr.y * config.znear,
     };
     let zfl = V2 {
-        x: zdl.x * ZFAR,
-        y: zdl.y * ZFAR,
+        x: zdl.x * config.zfar,
+        y: zdl.y * config.zfar,
     };
     let zfr = V2 {
-        x: zdr.x * ZFAR,
-        y: zdr.y * ZFAR,
+        x: zdr.x * config.zfar,
+        y: zdr.y * config.zfar,
     };
 
-    let mut queue = [QueueEntry {
-        id: state.camera.sector as usize,
-        x0: 0,
-        x1: (SCREEN_WIDTH - 1) as i32,
-    }];
-    let mut queue_len = 1;
+    // every portal wall is queued at most once for each camera, since its
+    // sector is only drawn once by each, and only if it is seen within the
+    // strip
+    let mut queue = Vec::with_capacity(view.walls.len() + 1);
+    queue.push(QueueEntry {
+        id: view.camera.sector as usize,
+        x0: strip.x0,
+        x1: strip.x1,
+        left: 0,
+        right: (width - 1) as i32,
+        camera: 0,
+    });
 
     #[derive(Clone, Copy)]
     struct QueueEntry {
         id: usize,
+        /// the columns of the strip the sector is seen through
         x0: i32,
         x1: i32,
+        /// the same across the whole screen, whose edges are shaded darker
+        left: i32,
+        right: i32,
+        camera: usize,
     }
 
-    while queue_len != 0 {
-        queue_len -= 1;
-        let entry = queue[queue_len];
-
-        if sectdraw[entry.id] {
+    while let Some(entry) = queue.pop() {
+        if sectdraw[entry.camera][entry.id] {
             continue;
         }
 
-        sectdraw[entry.id] = true;
+        sectdraw[entry.camera][entry.id] = true;
+        let (camera, depth) = cameras[entry.camera].clone();
+        let camera = &camera;
 
-        let sector = &state.sectors.arr[entry.id];
+        if view.occupied[entry.id] {
+            let (i0, i1) = (
+                (entry.x0 - strip.x0) as usize,
+                (entry.x1 - strip.x0) as usize,
+            );
+            windows.push(SectorWindow {
+                sector: entry.id,
+                camera: camera.clone(),
+                x0: entry.x0,
+                x1: entry.x1,
+                y_lo: strip.y_lo[i0..=i1].to_vec(),
+                y_hi: strip.y_hi[i0..=i1].to_vec(),
+            });
+        }
+
+        let sector = &view.sectors.arr[entry.id];
 
         for i in 0..sector.nwalls {
-            let wall = &state.walls.arr[sector.firstwall + i];
-            let op0 = world_pos_to_camera(wall.a.clone().v2i_to_v2(), state.clone().to_owned());
-            let op1 = world_pos_to_camera(wall.b.clone().v2i_to_v2(), state.clone().to_owned());
+            let wall = &view.walls.arr[sector.firstwall + i];
+
+            // past the last transforming portal looked through is a wall, and
+            // a mirror is a portal back into its own sector until the last
+            // one looked through
+            let mirror = wall.mirror && wall.portal == 0;
+            let portal = match wall.transform {
+                Some(_) if depth >= PORTAL_DEPTH_MAX => 0,
+                _ if mirror && camera.reflections < MIRROR_DEPTH_MAX => entry.id,
+                _ => wall.portal,
+            };
 
-            let mut cp0 = op0;
+            // a mirrored camera sees walls from `b` to `a` left to right
+            let (pa, pb) = match camera.mirrored() {
+                true => (&wall.b, &wall.a),
+                false => (&wall.a, &wall.b),
+            };
+            let op0 = world_pos_to_camera(pa.clone().v2i_to_v2(), camera);
+            let op1 = world_pos_to_camera(pb.clone().v2i_to_v2(), camera);
+
+            let mut cp0 = op0.clone();
             let mut cp1 = op1;
 
             if cp0.y <= 0.0 && cp1.y <= 0.0 {
                 continue;
             }
 
-            let ap0 = normalize_angle(f32::atan2(cp0.y, cp0.x) - PI_2);
-            let ap1 = normalize_angle(f32::atan2(cp1.y, cp1.x) - PI_2);
+            let mut ap0 = normalize_angle(f32::atan2(cp0.y, cp0.x) - PI_2);
+            let mut ap1 = normalize_angle(f32::atan2(cp1.y, cp1.x) - PI_2);
 
-            if cp0.y < ZNEAR || cp1.y < ZNEAR || ap0 > HFOV / 2.0 || ap1 < -HFOV / 2.0 {
+            if cp0.y < config.znear
+                || cp1.y < config.znear
+                || ap0 > config.hfov / 2.0
+                || ap1 < -config.hfov / 2.0
+            {
                 let il = intersect_segs(&cp0, &cp1, &znl, &zfl);
                 let ir = intersect_segs(&cp0, &cp1, &znr, &zfr);
 
+                // recompute angles if points change
                 if !il.x.is_nan() {
                     cp0 = il;
+                    ap0 = normalize_angle(f32::atan2(cp0.y, cp0.x) - PI_2);
                 }
 
                 if !ir.x.is_nan() {
                     cp1 = ir;
+                    ap1 = normalize_angle(f32::atan2(cp1.y, cp1.x) - PI_2);
                 }
             }
 
@@ -263,46 +970,75 @@ pub fn render(state: &mut State) {
                 continue;
             }
 
-            if (ap0 < -HFOV / 2.0 && ap1 < -HFOV / 2.0) || (ap0 > HFOV / 2.0 && ap1 > HFOV / 2.0) {
+            if (ap0 < -config.hfov / 2.0 && ap1 < -config.hfov / 2.0)
+                || (ap0 > config.hfov / 2.0 && ap1 > config.hfov / 2.0)
+            {
                 continue;
             }
 
-            let tx0 = screen_angle_to_x(ap0);
-            let tx1 = screen_angle_to_x(ap1);
+            let tx0 = screen_angle_to_x(ap0, &config);
+            let tx1 = screen_angle_to_x(ap1, &config);
 
             if tx0 > entry.x1 || tx1 < entry.x0 {
                 continue;
             }
 
-            let wallshade = 16
+            let wallshade = (16.0
                 * (f32::sin(f32::atan2(
                     wall.b.clone().v2i_to_v2().x - wall.a.clone().v2i_to_v2().x,
                     wall.b.clone().v2i_to_v2().y - wall.a.clone().v2i_to_v2().y,
-                )) + 1.0) as i32;
+                )) + 1.0)) as i32;
             let x0 = clamp(tx0, entry.x0, entry.x1);
             let x1 = clamp(tx1, entry.x0, entry.x1);
-            let z_floor = sector.zfloor;
-            let z_ceil = sector.zceil;
-            let nz_floor = if wall.portal != 0 {
-                state.sectors.arr[wall.portal].zfloor
-            } else {
-                0.0
+            let left = clamp(tx0, entry.left, entry.right);
+            let right = clamp(tx1, entry.left, entry.right);
+
+            // texture coordinates along the wall at the clipped ends, always
+            // from `a`, divided by depth so they can be interpolated linearly
+            // across the screen
+            let (a, b) = (wall.a.clone().v2i_to_v2(), wall.b.clone().v2i_to_v2());
+            let wall_length = length(V2::new(b.x - a.x, b.y - a.y));
+            let u0 = length(V2::new(cp0.x - op0.x, cp0.y - op0.y));
+            let u1 = length(V2::new(cp1.x - op0.x, cp1.y - op0.y));
+            let (u0, u1) = match camera.mirrored() {
+                true => (wall_length - u0, wall_length - u1),
+                false => (u0, u1),
             };
-            let nz_ceil = if wall.portal != 0 {
-                state.sectors.arr[wall.portal].zceil
+            let (iz0, iz1) = (1.0 / cp0.y, 1.0 / cp1.y);
+
+            // floor and ceiling heights at either end of the wall, on this
+            // side and behind a portal, and `u` along the wall in between,
+            // where they slope
+            let ends = |sector: &Sector| {
+                (
+                    (
+                        sector.floor_at(view.walls, &a),
+                        sector.floor_at(view.walls, &b),
+                    ),
+                    (
+                        sector.ceil_at(view.walls, &a),
+                        sector.ceil_at(view.walls, &b),
+                    ),
+                )
+            };
+            let along = |(za, zb): (f32, f32), u: f32| lerp(za, zb, u / wall_length);
+            let (floor_ends, ceil_ends) = ends(sector);
+            let (nfloor_ends, nceil_ends) = if portal != 0 {
+                ends(&view.sectors.arr[portal])
             } else {
-                0.0
+                ((0.0, 0.0), (0.0, 0.0))
             };
-            let sy0 = ifnan((VFOV * SCREEN_HEIGHT as f32) / cp0.y, 1e10);
-            let sy1 = ifnan((VFOV * SCREEN_HEIGHT as f32) / cp1.y, 1e10);
-            let yf0 = (SCREEN_HEIGHT / 2) + ((z_floor - EYE_Z) * sy0) as i32;
-            let yc0 = (SCREEN_HEIGHT / 2) + ((z_ceil - EYE_Z) * sy0) as i32;
-            let yf1 = (SCREEN_HEIGHT / 2) + ((z_floor - EYE_Z) * sy1) as i32;
-            let yc1 = (SCREEN_HEIGHT / 2) + ((z_ceil - EYE_Z) * sy1) as i32;
-            let nyf0 = (SCREEN_HEIGHT / 2) + ((nz_floor - EYE_Z) * sy0) as i32;
-            let nyc0 = (SCREEN_HEIGHT / 2) + ((nz_ceil - EYE_Z) * sy0) as i32;
-            let nyf1 = (SCREEN_HEIGHT / 2) + ((nz_floor - EYE_Z) * sy1) as i32;
-            let nyc1 = (SCREEN_HEIGHT / 2) + ((nz_ceil - EYE_Z) * sy1) as i32;
+
+            let sy0 = ifnan((config.vfov * height as f32) / cp0.y, 1e10);
+            let sy1 = ifnan((config.vfov * height as f32) / cp1.y, 1e10);
+            let yf0 = (height / 2) + ((along(floor_ends, u0) - camera.z) * sy0) as i32;
+            let yc0 = (height / 2) + ((along(ceil_ends, u0) - camera.z) * sy0) as i32;
+            let yf1 = (height / 2) + ((along(floor_ends, u1) - camera.z) * sy1) as i32;
+            let yc1 = (height / 2) + ((along(ceil_ends, u1) - camera.z) * sy1) as i32;
+            let nyf0 = (height / 2) + ((along(nfloor_ends, u0) - camera.z) * sy0) as i32;
+            let nyc0 = (height / 2) + ((along(nceil_ends, u0) - camera.z) * sy0) as i32;
+            let nyf1 = (height / 2) + ((along(nfloor_ends, u1) - camera.z) * sy1) as i32;
+            let nyc1 = (height / 2) + ((along(nceil_ends, u1) - camera.z) * sy1) as i32;
             let txd = tx1 - tx0;
             let yfd = yf1 - yf0;
             let ycd = yc1 - yc0;
@@ -310,165 +1046,343 @@ pub fn render(state: &mut State) {
             let nycd = nyc1 - nyc0;
 
             for x in x0..=x1 {
-                let shade = if x == x0 || x == x1 {
+                // where the column is kept in the strip
+                let sx = x - strip.x0;
+
+                // walls are lit by their sector, a little less when facing
+                // away from +X, and the edge columns darker still
+                let facing = if x == left || x == right {
                     192
                 } else {
                     255 - wallshade
                 };
+                let light = sector.light * facing as f32 / 255.0;
 
-                let xp = ifnan(((x - tx0) / txd) as f32, 0.0);
+                let xp = ifnan((x - tx0) as f32 / txd as f32, 0.0);
                 let tyf = (xp * yfd as f32) as i32 + yf0;
                 let tyc = (xp * ycd as f32) as i32 + yc0;
                 let yf = clamp(
                     tyf,
-                    state.y_lo[x as usize].into(),
-                    state.y_hi[x as usize].into(),
+                    strip.y_lo[sx as usize].into(),
+                    strip.y_hi[sx as usize].into(),
                 );
                 let yc = clamp(
                     tyc,
-                    state.y_lo[x as usize].into(),
-                    state.y_hi[x as usize].into(),
+                    strip.y_lo[sx as usize].into(),
+                    strip.y_hi[sx as usize].into(),
                 );
 
-                if yf > state.y_lo[x as usize].into() {
-                    verline(
-                        x,
-                        state.y_lo[x as usize].into(),
+                let u_at = |xp: f32| lerp(u0 * iz0, u1 * iz1, xp) / lerp(iz0, iz1, xp);
+                let u = u_at(xp);
+                let depth = 1.0 / lerp(iz0, iz1, xp);
+                let xp_next = ifnan((x + 1 - tx0) as f32 / txd as f32, 0.0);
+                let (z_floor, z_ceil) = (along(floor_ends, u), along(ceil_ends, u));
+
+                // textures run down from the ceiling
+                let column = WallColumn {
+                    u,
+                    du: (u_at(xp_next) - u).abs(),
+                    dz: if tyc != tyf {
+                        (z_ceil - z_floor) / (tyc - tyf) as f32
+                    } else {
+                        0.0
+                    },
+                    y_ref: tyf,
+                    z_ref: z_floor,
+                    z_top: z_ceil,
+                    shading: Shading::new(light, depth, view.fog, config.zfar)
+                        .tinted(camera.reflections),
+                };
+
+                let ray = ColumnRay {
+                    camera,
+                    config: &config,
+                    angle: match camera.mirrored() {
+                        true => camera.angle - view.angles[x as usize],
+                        false => camera.angle + view.angles[x as usize],
+                    },
+                    dir: view.dirs[x as usize],
+                    ddir: view.dirs[x as usize + 1] - view.dirs[x as usize],
+                };
+
+                if yf > strip.y_lo[sx as usize].into() {
+                    let floor = Plane {
+                        z: sector.floor_at(view.walls, &camera.pos),
+                        gradient: sector.floor_gradient(view.walls),
+                        texture: view.textures.get(sector.floor),
+                        color: 0xFFFF0000,
+                        light: sector.light,
+                        fog: view.fog,
+                    };
+                    plane_line(
+                        sx,
+                        strip.y_lo[sx as usize].into(),
                         yf,
-                        0xFFFF0000,
-                        &mut state.clone().to_owned(),
+                        &floor,
+                        &ray,
+                        strip.framebuffer,
+                        strip.depth,
                     );
                 }
 
-                if yc < state.y_hi[x as usize] as i32 {
-                    verline(
-                        x,
-                        yc,
-                        state.y_hi[x as usize].into(),
-                        0xFF00FFFF,
-                        &mut state.clone().to_owned(),
-                    );
+                if yc < strip.y_hi[sx as usize] as i32 {
+                    if sector.sky {
+                        sky_line(
+                            sx,
+                            yc,
+                            strip.y_hi[sx as usize].into(),
+                            view.sky,
+                            &ray,
+                            strip.framebuffer,
+                        );
+                    } else {
+                        let ceil = Plane {
+                            z: sector.ceil_at(view.walls, &camera.pos),
+                            gradient: sector.ceil_gradient(view.walls),
+                            texture: view.textures.get(sector.ceil),
+                            color: 0xFF00FFFF,
+                            light: sector.light,
+                            fog: view.fog,
+                        };
+                        plane_line(
+                            sx,
+                            yc,
+                            strip.y_hi[sx as usize].into(),
+                            &ceil,
+                            &ray,
+                            strip.framebuffer,
+                            strip.depth,
+                        );
+                    }
                 }
 
-                if wall.portal != 0 {
+                if portal != 0 {
                     let tnyf = (xp * nyfd as f32) as i32 + nyf0;
                     let tnyc = (xp * nycd as f32) as i32 + nyc0;
                     let nyf = clamp(
                         tnyf,
-                        state.y_lo[x as usize].into(),
-                        state.y_hi[x as usize].into(),
+                        strip.y_lo[sx as usize].into(),
+                        strip.y_hi[sx as usize].into(),
                     );
                     let nyc = clamp(
                         tnyc,
-                        state.y_lo[x as usize].into(),
-                        state.y_hi[x as usize].into(),
+                        strip.y_lo[sx as usize].into(),
+                        strip.y_hi[sx as usize].into(),
                     );
 
-                    verline(
-                        x,
-                        nyc,
-                        yc,
-                        abgr_mul(0xFF00FF00, shade as u32),
-                        &mut state.clone(),
-                    ); // Black Magic
-                    verline(
-                        x,
+                    // between two skies the step down is more sky
+                    if sector.sky && view.sectors.arr[portal].sky {
+                        sky_line(sx, nyc, yc, view.sky, &ray, strip.framebuffer);
+                    } else {
+                        wall_line(
+                            sx,
+                            nyc,
+                            yc,
+                            view.textures.get(wall.upper),
+                            0xFF00FF00,
+                            &column,
+                            strip.framebuffer,
+                        ); // Black Magic
+                        strip.depth.verline(sx, nyc, yc, depth);
+                    }
+                    wall_line(
+                        sx,
                         yf,
                         nyf,
-                        abgr_mul(0xFF0000FF, shade as u32),
-                        &mut state.clone(),
+                        view.textures.get(wall.lower),
+                        0xFF0000FF,
+                        &column,
+                        strip.framebuffer,
                     ); // No touch
+                    strip.depth.verline(sx, yf, nyf, depth);
 
-                    state.y_hi[x as usize] = clamp(
+                    strip.y_hi[sx as usize] = clamp(
                         min(
                             min(yc.try_into().unwrap(), nyc.try_into().unwrap()),
-                            state.y_hi[x as usize],
+                            strip.y_hi[sx as usize],
                         ),
                         0,
-                        (SCREEN_HEIGHT - 1) as u16,
+                        (height - 1) as u16,
                     );
-                    state.y_lo[x as usize] = clamp(
+                    strip.y_lo[sx as usize] = clamp(
                         max(
                             max(yf.try_into().unwrap(), nyf.try_into().unwrap()),
-                            state.y_lo[x as usize],
+                            strip.y_lo[sx as usize],
                         ),
                         0,
-                        (SCREEN_HEIGHT - 1) as u16,
+                        (height - 1) as u16,
                     );
+
+                    // steps can meet and close the column too
+                    if strip.y_lo[sx as usize] >= strip.y_hi[sx as usize] {
+                        strip.depth.close_column(sx, depth);
+                    }
                 } else {
-                    verline(
-                        x,
+                    wall_line(
+                        sx,
                         yf,
                         yc,
-                        abgr_mul(0xFFD0D0D0, shade as u32),
-                        &mut state.clone(),
+                        view.textures.get(wall.texture),
+                        if mirror { MIRROR_TINT } else { 0xFFD0D0D0 },
+                        &column,
+                        strip.framebuffer,
                     );
+                    strip.depth.verline(sx, yf, yc, depth);
+                    strip.depth.close_column(sx, depth);
                 }
 
-                if state.sleepy {
-                    present(&mut state.clone());
+                if let Some(presenter) = &mut presenter {
+                    presenter.present(strip.framebuffer);
                     let ten_millis = std::time::Duration::from_millis(10);
 
                     std::thread::sleep(ten_millis)
                 }
             }
 
-            if wall.portal != 0 {
-                assert!(queue_len != QUEUE_MAX as usize, "out of queue space");
-                queue[queue_len] = QueueEntry {
-                    id: wall.portal,
+            if portal != 0 {
+                // the sector behind a transforming portal is somewhere else
+                // in the map, and seen by a camera carried through to there;
+                // the one in a mirror is seen by a camera reflected in it
+                let next = match &wall.transform {
+                    Some(transform) => Some((transform.apply_camera(camera), depth + 1)),
+                    None if mirror => Some((camera.reflect(&a, &b), depth)),
+                    None => None,
+                };
+                let camera = match next {
+                    Some(next) => {
+                        cameras.push(next);
+                        sectdraw.push(vec![false; view.sectors.len()]);
+                        cameras.len() - 1
+                    }
+                    None => entry.camera,
+                };
+                queue.push(QueueEntry {
+                    id: portal,
                     x0,
                     x1,
-                };
-                queue_len += 1;
+                    left,
+                    right,
+                    camera,
+                });
             }
         }
     }
 
-    state.sleepy = false;
+    draw_things(view, strip, &windows);
 }
 
-pub fn present(state: &mut State) {
-    let mut px: *mut std::ffi::c_void = std::ptr::null_mut();
-    let mut pitch: i32 = 0;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::res::level::save::save_level;
+    use crate::res::level::validate::LevelProblem;
+    use crate::res::testing::{root, TempFile};
 
-    unsafe {
-        SDL_LockTexture(state.texture, std::ptr::null(), &mut px, &mut pitch);
-        let px_slice = slice::from_raw_parts_mut(px as *mut u8, (SCREEN_HEIGHT * pitch) as usize);
-        let pixels_slice = slice::from_raw_parts(
-            state.pixels.as_ptr() as *const u8,
-            SCREEN_WIDTH * SCREEN_HEIGHT as usize * 4,
-        );
-        px_slice.copy_from_slice(pixels_slice);
-        SDL_UnlockTexture(state.texture);
-
-        SDL_SetRenderTarget(state.renderer, std::ptr::null_mut());
-        SDL_SetRenderDrawColor(state.renderer, 0, 0, 0, 0xFF);
-        SDL_SetRenderDrawBlendMode(state.renderer, sdl2_sys::SDL_BlendMode::SDL_BLENDMODE_NONE);
-
-        SDL_RenderClear(state.renderer);
-        SDL_RenderCopyEx(
-            state.renderer,
-            state.texture,
-            std::ptr::null(),
-            std::ptr::null(),
-            0.0,
-            std::ptr::null(),
-            sdl2_sys::SDL_RendererFlip::SDL_FLIP_VERTICAL,
-        );
+    #[test]
+    fn load_places_the_camera() {
+        let mut state = State::new();
+        let level = root().join("level.txt");
+        load_sectors(level.to_str().unwrap(), &mut state).unwrap();
+        assert_eq!(state.camera.pos, V2::new(3.0, 3.0));
+        assert_eq!(state.camera.sector, 1);
+    }
 
-        SDL_SetTextureBlendMode(state.debug, sdl2_sys::SDL_BlendMode::SDL_BLENDMODE_BLEND);
-        SDL_RenderCopy(
-            state.renderer,
-            state.debug,
-            std::ptr::null(),
-            &SDL_Rect {
-                x: 0,
-                y: 0,
-                w: 512,
-                h: 512,
-            },
-        );
-        SDL_RenderPresent(state.renderer);
+    #[test]
+    fn edits_save_from_the_state() {
+        let mut state = State::new();
+        let level = root().join("level.txt");
+        load_sectors(level.to_str().unwrap(), &mut state).unwrap();
+        state.sectors.arr[1].zceil += 2.0;
+        state.title = Some("Raised".to_string());
+
+        let file = TempFile::new("edited.txt");
+        save_level(file.path(), &state.level()).unwrap();
+
+        let mut saved = State::new();
+        load_sectors(file.path(), &mut saved).unwrap();
+        assert_eq!(saved.sectors, state.sectors);
+        assert_eq!(saved.walls, state.walls);
+        assert_eq!(saved.title.as_deref(), Some("Raised"));
+        assert_eq!(saved.player, state.player);
+    }
+
+    #[test]
+    fn load_rejects_invalid_levels_untouched() {
+        // four walls claimed, three given
+        let file = TempFile::new("short-sector.txt");
+        std::fs::write(
+            file.path(),
+            "[SECTOR]\n1 0 4 0 4\n\n[WALL]\n0 0 0 4 0\n0 4 4 4 0\n4 4 0 0 0\n\n[PLAYER]\n2 2 0\n",
+        )
+        .unwrap();
+
+        let mut state = State::new();
+        let level = root().join("level.txt");
+        load_sectors(level.to_str().unwrap(), &mut state).unwrap();
+        let (sectors, walls) = (state.sectors.clone(), state.walls.clone());
+
+        match load_sectors(file.path(), &mut state) {
+            Err(ReloadError::Invalid(problems)) => assert!(matches!(
+                problems[0],
+                LevelProblem::WallRange { sector: 1, .. }
+            )),
+            other => panic!("expected the level to be invalid, got {:?}", other),
+        }
+        assert_eq!(state.sectors, sectors);
+        assert_eq!(state.walls, walls);
+
+        assert!(matches!(
+            reload_sectors(file.path(), &mut state),
+            Err(ReloadError::Invalid(_))
+        ));
+        assert_eq!(state.sectors, sectors);
+        assert_eq!(state.walls, walls);
+    }
+
+    /// Two 4x4 rooms side by side, the camera in the west one facing the
+    /// east one, which has the given floor and ceiling.
+    fn two_rooms(name: &str, zfloor: f32, zceil: f32) -> (TempFile, State) {
+        let file = TempFile::new(name);
+        std::fs::write(
+            file.path(),
+            format!(
+                "[SECTOR]\n1 0 4 0 4\n2 4 4 {} {}\n\n\
+                 [WALL]\n0 0 0 4 0\n0 4 4 4 0\n4 4 4 0 2\n4 0 0 0 0\n\
+                 4 0 4 4 1\n4 4 8 4 0\n8 4 8 0 0\n8 0 4 0 0\n\n\
+                 [PLAYER]\n3 2 0\n",
+                zfloor, zceil
+            ),
+        )
+        .unwrap();
+
+        let mut state = State::new();
+        load_sectors(file.path(), &mut state).unwrap();
+        (file, state)
+    }
+
+    #[test]
+    fn camera_climbs_low_steps_and_drops_down() {
+        for zfloor in [STEP_HEIGHT, -2.0] {
+            let (_file, mut state) = two_rooms("step.txt", zfloor, 4.0);
+            move_camera(&mut state, V2::new(2.0, 0.0));
+            assert_eq!(state.camera.pos, V2::new(5.0, 2.0));
+        }
+    }
+
+    #[test]
+    fn camera_stops_at_high_steps_and_low_ceilings() {
+        for (zfloor, zceil) in [(STEP_HEIGHT + 0.5, 4.0), (0.0, EYE_Z)] {
+            let (_file, mut state) = two_rooms("blocked.txt", zfloor, zceil);
+            move_camera(&mut state, V2::new(2.0, 0.0));
+            assert_eq!(state.camera.pos, V2::new(3.0, 2.0));
+        }
+    }
+
+    #[test]
+    fn camera_slides_along_solid_walls() {
+        let (_file, mut state) = two_rooms("wall.txt", 0.0, 4.0);
+        move_camera(&mut state, V2::new(-1.0, 3.0));
+        assert_eq!(state.camera.pos, V2::new(2.0, 2.0));
+        assert_eq!(state.camera.sector, 1);
     }
 }
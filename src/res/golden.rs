@@ -0,0 +1,387 @@
+//! Golden-image tests for the renderer. Each viewpoint is rendered headless
+//! from its level, with the built-in textures, and compared against
+//! `tests/golden/<name>.ppm`.
+//!
+//! After an intentional change to the output, regenerate the references with
+//! `ZENGINE_BLESS=1 cargo test golden` and look at them before committing.
+//! On a mismatch the rendered frame and a diff image (differing pixels in
+//! red over a faded copy of the reference) are written to `target/golden/`.
+//!
+//! Every viewpoint is also drawn in strips on several threads, which has to
+//! give exactly the same frame and depth buffer as drawing it in one go.
+
+use std::f32::consts::{FRAC_PI_3, PI};
+use std::fs;
+use std::path::Path;
+
+use super::framebuffer::{DepthBuffer, Framebuffer};
+use super::game::{load_sectors, locate_sector, render, update_camera_z, State};
+use super::present::headless::HeadlessPresenter;
+use super::screenshot::{rgb, save_screenshot};
+use super::testing::root;
+use super::texture::{load_image, Textures};
+use super::util::kinds::{Fog, RenderConfig, V2};
+use super::util::math::deg_2_rad;
+
+const LEVEL: &str = "level.txt";
+const TEXTURED: &str = "tests/levels/textured.txt";
+const SLOPED: &str = "tests/levels/sloped.txt";
+const WARP: &str = "tests/levels/warp.txt";
+const MIRROR: &str = "tests/levels/mirror.txt";
+
+/// Largest difference allowed in any channel of a pixel.
+const TOLERANCE: u8 = 2;
+
+struct Viewpoint {
+    level: &'static str,
+    name: &'static str,
+    pos: (f32, f32),
+    /// degrees
+    angle: f32,
+    fog: Option<Fog>,
+    /// the default if not given
+    config: Option<RenderConfig>,
+}
+
+const VIEWPOINTS: [Viewpoint; 21] = [
+    Viewpoint {
+        level: LEVEL,
+        name: "start",
+        pos: (3.0, 3.0),
+        angle: 90.0,
+        fog: None,
+        config: None,
+    },
+    Viewpoint {
+        level: LEVEL,
+        name: "east_portal",
+        pos: (3.0, 3.0),
+        angle: 20.0,
+        fog: None,
+        config: None,
+    },
+    Viewpoint {
+        level: LEVEL,
+        name: "west_portal",
+        pos: (3.0, 3.0),
+        angle: 200.0,
+        fog: None,
+        config: None,
+    },
+    Viewpoint {
+        level: LEVEL,
+        name: "through_two",
+        pos: (5.5, 4.5),
+        angle: 60.0,
+        fog: None,
+        config: None,
+    },
+    Viewpoint {
+        level: LEVEL,
+        name: "near_wall",
+        pos: (4.4, 1.9),
+        angle: -20.0,
+        fog: None,
+        config: None,
+    },
+    Viewpoint {
+        level: TEXTURED,
+        name: "textured_start",
+        pos: (3.0, 3.0),
+        angle: 90.0,
+        fog: None,
+        config: None,
+    },
+    Viewpoint {
+        level: TEXTURED,
+        name: "textured_steps",
+        pos: (3.0, 3.0),
+        angle: 200.0,
+        fog: None,
+        config: None,
+    },
+    Viewpoint {
+        level: TEXTURED,
+        name: "textured_oblique",
+        pos: (2.0, 2.0),
+        angle: 10.0,
+        fog: None,
+        config: None,
+    },
+    Viewpoint {
+        level: TEXTURED,
+        name: "textured_sky",
+        pos: (5.5, 4.5),
+        angle: 60.0,
+        fog: None,
+        config: None,
+    },
+    Viewpoint {
+        level: TEXTURED,
+        name: "textured_fog",
+        pos: (2.0, 2.0),
+        angle: 10.0,
+        fog: Some(Fog {
+            color: 0xFF808080,
+            start: 1.0,
+            density: 0.3,
+        }),
+        config: None,
+    },
+    Viewpoint {
+        level: LEVEL,
+        name: "small_narrow",
+        pos: (3.0, 3.0),
+        angle: 30.0,
+        fog: None,
+        config: Some(RenderConfig {
+            hfov: FRAC_PI_3,
+            ..RenderConfig::new(320, 200)
+        }),
+    },
+    Viewpoint {
+        level: TEXTURED,
+        name: "textured_wide",
+        pos: (2.0, 2.0),
+        angle: 40.0,
+        fog: None,
+        config: Some(RenderConfig {
+            hfov: PI * 2.0 / 3.0,
+            vfov: 0.4,
+            ..RenderConfig::new(480, 270)
+        }),
+    },
+    Viewpoint {
+        level: SLOPED,
+        name: "sloped_ramp",
+        pos: (1.0, 2.0),
+        angle: 0.0,
+        fog: None,
+        config: None,
+    },
+    Viewpoint {
+        level: SLOPED,
+        name: "sloped_top",
+        pos: (13.0, 1.5),
+        angle: 170.0,
+        fog: None,
+        config: None,
+    },
+    Viewpoint {
+        level: SLOPED,
+        name: "sloped_on_ramp",
+        pos: (5.0, 3.5),
+        angle: 20.0,
+        fog: None,
+        config: None,
+    },
+    Viewpoint {
+        level: WARP,
+        name: "warp_corridor",
+        pos: (1.0, 2.0),
+        angle: 0.0,
+        fog: None,
+        config: None,
+    },
+    Viewpoint {
+        level: WARP,
+        name: "warp_back",
+        pos: (3.0, 1.0),
+        angle: 160.0,
+        fog: None,
+        config: None,
+    },
+    Viewpoint {
+        level: WARP,
+        name: "warp_far_room",
+        pos: (22.0, 15.0),
+        angle: 75.0,
+        fog: None,
+        config: None,
+    },
+    Viewpoint {
+        level: MIRROR,
+        name: "mirror_facing",
+        pos: (4.5, 1.5),
+        angle: 180.0,
+        fog: None,
+        config: None,
+    },
+    Viewpoint {
+        level: MIRROR,
+        name: "mirror_angled",
+        pos: (3.0, 1.0),
+        angle: 145.0,
+        fog: None,
+        config: None,
+    },
+    Viewpoint {
+        level: MIRROR,
+        name: "mirror_east",
+        pos: (4.0, 3.0),
+        angle: 20.0,
+        fog: None,
+        config: None,
+    },
+];
+
+/// Renders `view` into a fresh state, keeping per-pixel depths, and hands
+/// the state back.
+fn render_viewpoint_with(view: &Viewpoint, config: RenderConfig) -> State {
+    let mut state = State::with_config(config);
+    state.depth = DepthBuffer::new(config.width, config.height, true);
+    state.textures = Textures::builtin();
+    let level = root().join(view.level);
+    load_sectors(level.to_str().unwrap(), &mut state).unwrap_or_else(|e| panic!("{}", e));
+
+    let pos = V2::new(view.pos.0, view.pos.1);
+    let sector = locate_sector(&pos, &mut state)
+        .unwrap_or_else(|| panic!("viewpoint {} is not inside any sector", view.name));
+
+    let angle = deg_2_rad(view.angle);
+    state.camera.pos = pos;
+    state.camera.angle = angle;
+    state.camera.anglecos = angle.cos();
+    state.camera.anglesin = angle.sin();
+    state.camera.sector = sector as i32;
+    update_camera_z(&mut state);
+    state.fog = view.fog.clone();
+
+    render(&mut state, &mut HeadlessPresenter::new());
+    state
+}
+
+fn render_viewpoint(view: &Viewpoint) -> Framebuffer {
+    render_viewpoint_with(view, view.config.unwrap_or_default()).framebuffer
+}
+
+/// Reads a reference image written by `save_screenshot` back into a
+/// framebuffer.
+fn read_reference(path: &Path) -> Result<Framebuffer, String> {
+    let image = load_image(path.to_str().unwrap()).map_err(|e| e.to_string())?;
+
+    // stored top-down, framebuffer row 0 is the bottom
+    let mut frame = Framebuffer::new(image.width, image.height);
+    for (dst, src) in frame
+        .pixels
+        .chunks_exact_mut(image.width)
+        .rev()
+        .zip(image.pixels.chunks_exact(image.width))
+    {
+        dst.copy_from_slice(src);
+    }
+
+    Ok(frame)
+}
+
+/// Counts pixels with a channel more than `TOLERANCE` off, and builds the
+/// diff image.
+fn compare(expected: &Framebuffer, actual: &Framebuffer) -> (usize, Framebuffer) {
+    let mut diff = Framebuffer::new(expected.width, expected.height);
+    let mut bad = 0;
+
+    for ((d, &e), &a) in diff
+        .pixels
+        .iter_mut()
+        .zip(&expected.pixels)
+        .zip(&actual.pixels)
+    {
+        let off = rgb(e)
+            .iter()
+            .zip(rgb(a))
+            .any(|(&e, a)| e.abs_diff(a) > TOLERANCE);
+
+        *d = if off {
+            bad += 1;
+            0xFF0000FF
+        } else {
+            let [r, g, b] = rgb(e);
+            let grey = (r as u32 + g as u32 + b as u32) / 12;
+            0xFF000000 | grey << 16 | grey << 8 | grey
+        };
+    }
+
+    (bad, diff)
+}
+
+#[test]
+fn golden_images() {
+    let references = root().join("tests").join("golden");
+    let output = root().join("target").join("golden");
+    let bless = std::env::var_os("ZENGINE_BLESS").is_some();
+
+    let mut failures = Vec::new();
+
+    for view in &VIEWPOINTS {
+        let actual = render_viewpoint(view);
+        let reference = references.join(format!("{}.ppm", view.name));
+
+        if bless {
+            fs::create_dir_all(&references).unwrap();
+            save_screenshot(&reference, &actual).unwrap();
+            continue;
+        }
+
+        let expected = match read_reference(&reference) {
+            Ok(expected) => expected,
+            Err(e) => {
+                failures.push(e);
+                continue;
+            }
+        };
+
+        if (expected.width, expected.height) != (actual.width, actual.height) {
+            failures.push(format!(
+                "{}: rendered {}x{}, reference is {}x{}",
+                view.name, actual.width, actual.height, expected.width, expected.height
+            ));
+            continue;
+        }
+
+        let (bad, diff) = compare(&expected, &actual);
+        if bad > 0 {
+            fs::create_dir_all(&output).unwrap();
+            let actual_path = output.join(format!("{}.ppm", view.name));
+            let diff_path = output.join(format!("{}.diff.ppm", view.name));
+            save_screenshot(&actual_path, &actual).unwrap();
+            save_screenshot(&diff_path, &diff).unwrap();
+            failures.push(format!(
+                "{}: {} pixels differ, see {}",
+                view.name,
+                bad,
+                diff_path.display()
+            ));
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "golden images differ:\n{}",
+        failures.join("\n")
+    );
+}
+
+#[test]
+fn threaded_matches_serial() {
+    for view in &VIEWPOINTS {
+        let config = view.config.unwrap_or_default();
+        let serial = render_viewpoint_with(view, config);
+
+        // strips of uneven widths as well as even ones
+        for threads in [2, 3, 7] {
+            let threaded = render_viewpoint_with(view, RenderConfig { threads, ..config });
+            assert!(
+                threaded.framebuffer == serial.framebuffer,
+                "{}: {} threads drew a different frame",
+                view.name,
+                threads
+            );
+            assert!(
+                threaded.depth == serial.depth,
+                "{}: {} threads left a different depth buffer",
+                view.name,
+                threads
+            );
+        }
+    }
+}
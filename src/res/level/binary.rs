@@ -0,0 +1,643 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::mem::{align_of, size_of};
+use std::slice;
+
+use memmap2::Mmap;
+
+use super::error::LevelError;
+use super::portals::LinkMode;
+use super::{read_level, read_level_with, Level, PlayerStart};
+use crate::internaltypes::thing::Thing;
+use crate::res::util::kinds::{PortalTransform, Sector, Sectors, Slope, V2i, Wall, Walls, V2};
+
+/// File extension of compiled levels.
+pub const EXTENSION: &str = "zlb";
+
+pub const MAGIC: [u8; 4] = *b"ZLVL";
+
+pub const VERSION: u32 = 11;
+
+/// `Header::flags` bits for levels with a title and a player start, and for
+/// player starts that give their sector.
+const LEVEL_TITLE: u32 = 1;
+const LEVEL_PLAYER: u32 = 2;
+const LEVEL_PLAYER_SECTOR: u32 = 4;
+
+/// `PackedSector::flags` bit for sectors open to the sky.
+const SECTOR_SKY: u32 = 1;
+
+/// `PackedSector::flags` bits for sloped floors and ceilings.
+const SECTOR_FLOOR_SLOPE: u32 = 2;
+const SECTOR_CEIL_SLOPE: u32 = 4;
+
+/// `PackedWall::flags` bit for portals with a transform.
+const WALL_TRANSFORM: u32 = 1;
+
+/// `PackedWall::flags` bit for mirrors.
+const WALL_MIRROR: u32 = 2;
+
+/// Start of a compiled level. The sector, wall, thing and string tables
+/// follow at the given byte offsets, every offset a multiple of 4 so the
+/// tables can be used straight out of the mapped file. Everything is
+/// little-endian.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Header {
+    magic: [u8; 4],
+    version: u32,
+    /// sectors in the table, not counting sector 0
+    nsectors: u32,
+    nwalls: u32,
+    sectors: u32,
+    walls: u32,
+    nthings: u32,
+    things: u32,
+    /// NUL terminated strings: the level's `ntextures` texture names in
+    /// order, then the names of its things, then its title if it has one
+    strings: u32,
+    strings_len: u32,
+    ntextures: u32,
+    flags: u32,
+    /// index into the string table, only meaningful if the flags say the
+    /// level has a title
+    title: u32,
+    /// only meaningful if the flags say the level has a player start
+    player: PackedPlayer,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct PackedPlayer {
+    /// f32 bits
+    x: u32,
+    /// f32 bits
+    y: u32,
+    /// f32 bits, radians
+    angle: u32,
+    /// only meaningful if the flags say the start gives its sector
+    sector: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct PackedSector {
+    id: i32,
+    firstwall: u32,
+    nwalls: u32,
+    /// f32 bits
+    zfloor: u32,
+    /// f32 bits
+    zceil: u32,
+    floor: u32,
+    ceil: u32,
+    /// f32 bits
+    light: u32,
+    flags: u32,
+    floor_slope: PackedSlope,
+    ceil_slope: PackedSlope,
+}
+
+/// Only meaningful if the sector's flags say it is sloped.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct PackedSlope {
+    wall: u32,
+    /// f32 bits
+    delta: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct PackedWall {
+    ax: i32,
+    ay: i32,
+    bx: i32,
+    by: i32,
+    portal: u32,
+    texture: u32,
+    upper: u32,
+    lower: u32,
+    flags: u32,
+    transform: PackedTransform,
+}
+
+/// Only meaningful if the wall's flags say it has a transform.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct PackedTransform {
+    /// f32 bits
+    x: u32,
+    /// f32 bits
+    y: u32,
+    /// f32 bits, radians
+    angle: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct PackedThing {
+    /// index into the string table
+    name: u32,
+    /// f32 bits
+    x: u32,
+    /// f32 bits
+    y: u32,
+    sprite: u32,
+    /// f32 bits
+    height: u32,
+}
+
+fn corrupt(path: &str, message: &str) -> LevelError {
+    LevelError::Corrupt {
+        path: path.to_string(),
+        message: message.to_string(),
+    }
+}
+
+/// Views `len` records at `offset` in the file without copying them.
+fn table<'a, T: Copy>(
+    path: &str,
+    bytes: &'a [u8],
+    offset: u32,
+    len: u32,
+    what: &str,
+) -> Result<&'a [T], LevelError> {
+    let start = offset as usize;
+    let end = (len as usize)
+        .checked_mul(size_of::<T>())
+        .and_then(|size| size.checked_add(start));
+
+    if end.is_none_or(|end| end > bytes.len()) {
+        return Err(corrupt(
+            path,
+            &format!("{} runs past the end of the file", what),
+        ));
+    }
+
+    let ptr = bytes[start..].as_ptr();
+    if !(ptr as usize).is_multiple_of(align_of::<T>()) {
+        return Err(corrupt(path, &format!("{} is misaligned", what)));
+    }
+
+    // SAFETY: in bounds and aligned as checked above, and the records are
+    // plain integers so any bit pattern is a valid value
+    Ok(unsafe { slice::from_raw_parts(ptr as *const T, len as usize) })
+}
+
+fn unpack_slope(slope: &PackedSlope, flags: u32, flag: u32) -> Option<Slope> {
+    (u32::from_le(flags) & flag != 0).then(|| Slope {
+        wall: u32::from_le(slope.wall) as usize,
+        delta: f32::from_bits(u32::from_le(slope.delta)),
+    })
+}
+
+fn unpack_transform(transform: &PackedTransform, flags: u32) -> Option<PortalTransform> {
+    (u32::from_le(flags) & WALL_TRANSFORM != 0).then(|| PortalTransform {
+        offset: V2::new(
+            f32::from_bits(u32::from_le(transform.x)),
+            f32::from_bits(u32::from_le(transform.y)),
+        ),
+        angle: f32::from_bits(u32::from_le(transform.angle)),
+    })
+}
+
+/// Reads a compiled level by mapping it into memory and copying the packed
+/// tables out, no parsing involved.
+pub fn read_binary(path: &str) -> Result<Level, LevelError> {
+    let f = File::open(path).map_err(|error| LevelError::open(path, error))?;
+
+    // SAFETY: the map is only read while this function runs; a level being
+    // rewritten underneath us at worst fails the checks below
+    let map = unsafe { Mmap::map(&f) }.map_err(|error| LevelError::open(path, error))?;
+    let bytes: &[u8] = &map;
+
+    let header = table::<Header>(path, bytes, 0, 1, "header")?[0];
+    if header.magic != MAGIC {
+        return Err(corrupt(path, "not a compiled level, bad magic"));
+    }
+
+    let version = u32::from_le(header.version);
+    if version != VERSION {
+        return Err(corrupt(
+            path,
+            &format!(
+                "level version {} is not supported, expected {}",
+                version, VERSION
+            ),
+        ));
+    }
+
+    let nsectors = u32::from_le(header.nsectors);
+    let nwalls = u32::from_le(header.nwalls);
+    let packed_sectors: &[PackedSector] = table(
+        path,
+        bytes,
+        u32::from_le(header.sectors),
+        nsectors,
+        "sector table",
+    )?;
+    let packed_walls: &[PackedWall] = table(
+        path,
+        bytes,
+        u32::from_le(header.walls),
+        nwalls,
+        "wall table",
+    )?;
+    let packed_things: &[PackedThing] = table(
+        path,
+        bytes,
+        u32::from_le(header.things),
+        u32::from_le(header.nthings),
+        "thing table",
+    )?;
+    let strings: &[u8] = table(
+        path,
+        bytes,
+        u32::from_le(header.strings),
+        u32::from_le(header.strings_len),
+        "string table",
+    )?;
+
+    let mut names = vec![];
+    if let Some(strings) = strings.strip_suffix(&[0]) {
+        for name in strings.split(|&b| b == 0) {
+            let name = std::str::from_utf8(name).map_err(|_| corrupt(path, "name is not UTF-8"))?;
+            names.push(name.to_string());
+        }
+    } else if !strings.is_empty() {
+        return Err(corrupt(path, "string table is not NUL terminated"));
+    }
+
+    let ntextures = u32::from_le(header.ntextures) as usize;
+    if ntextures > names.len() {
+        return Err(corrupt(path, "string table is missing texture names"));
+    }
+    let textures = names[..ntextures].to_vec();
+
+    let mut sectors = Sectors::new();
+    sectors.arr.extend(packed_sectors.iter().map(|s| Sector {
+        id: i32::from_le(s.id),
+        firstwall: u32::from_le(s.firstwall) as usize,
+        nwalls: u32::from_le(s.nwalls) as usize,
+        zfloor: f32::from_bits(u32::from_le(s.zfloor)),
+        zceil: f32::from_bits(u32::from_le(s.zceil)),
+        floor: u32::from_le(s.floor) as usize,
+        ceil: u32::from_le(s.ceil) as usize,
+        light: f32::from_bits(u32::from_le(s.light)),
+        sky: u32::from_le(s.flags) & SECTOR_SKY != 0,
+        floor_slope: unpack_slope(&s.floor_slope, s.flags, SECTOR_FLOOR_SLOPE),
+        ceil_slope: unpack_slope(&s.ceil_slope, s.flags, SECTOR_CEIL_SLOPE),
+    }));
+
+    let mut walls = Walls::new();
+    walls.arr.extend(packed_walls.iter().map(|w| Wall {
+        a: V2i::new(i32::from_le(w.ax), i32::from_le(w.ay)),
+        b: V2i::new(i32::from_le(w.bx), i32::from_le(w.by)),
+        portal: u32::from_le(w.portal) as usize,
+        texture: u32::from_le(w.texture) as usize,
+        upper: u32::from_le(w.upper) as usize,
+        lower: u32::from_le(w.lower) as usize,
+        transform: unpack_transform(&w.transform, w.flags),
+        mirror: u32::from_le(w.flags) & WALL_MIRROR != 0,
+    }));
+
+    let mut things = Vec::with_capacity(packed_things.len());
+    for t in packed_things {
+        let name = names
+            .get(u32::from_le(t.name) as usize)
+            .ok_or_else(|| corrupt(path, "thing name is out of range"))?;
+        let mut thing = Thing::new(name.clone());
+        thing.pos = V2::new(
+            f32::from_bits(u32::from_le(t.x)),
+            f32::from_bits(u32::from_le(t.y)),
+        );
+        thing.sprite = u32::from_le(t.sprite) as usize;
+        thing.height = f32::from_bits(u32::from_le(t.height));
+        things.push(thing);
+    }
+
+    let flags = u32::from_le(header.flags);
+    let title = match flags & LEVEL_TITLE != 0 {
+        true => Some(
+            names
+                .get(u32::from_le(header.title) as usize)
+                .ok_or_else(|| corrupt(path, "title is out of range"))?
+                .clone(),
+        ),
+        false => None,
+    };
+
+    let p = &header.player;
+    let player = (flags & LEVEL_PLAYER != 0).then(|| PlayerStart {
+        pos: V2::new(
+            f32::from_bits(u32::from_le(p.x)),
+            f32::from_bits(u32::from_le(p.y)),
+        ),
+        angle: f32::from_bits(u32::from_le(p.angle)),
+        sector: (flags & LEVEL_PLAYER_SECTOR != 0).then(|| u32::from_le(p.sector) as usize),
+    });
+
+    Ok(Level {
+        sectors,
+        walls,
+        title,
+        player,
+        things,
+        textures,
+    })
+}
+
+fn to_u32(n: usize, what: &str) -> io::Result<u32> {
+    u32::try_from(n).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} {} does not fit a compiled level", what, n),
+        )
+    })
+}
+
+pub fn write_binary(path: &str, level: &Level) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+
+    // sector 0 does not exist
+    let sectors = &level.sectors.arr[1..];
+    let walls = &level.walls.arr;
+
+    let names: Vec<String> = level
+        .textures
+        .iter()
+        .cloned()
+        .chain(level.things.iter().map(|thing| thing.clone().get_name()))
+        .chain(level.title.iter().cloned())
+        .collect();
+
+    let mut flags = 0;
+    if level.title.is_some() {
+        flags |= LEVEL_TITLE;
+    }
+    let player = level.player.clone().unwrap_or_default();
+    if level.player.is_some() {
+        flags |= LEVEL_PLAYER;
+    }
+    if player.sector.is_some() {
+        flags |= LEVEL_PLAYER_SECTOR;
+    }
+
+    let sector_table = size_of::<Header>();
+    let wall_table = sector_table + sectors.len() * size_of::<PackedSector>();
+    let thing_table = wall_table + walls.len() * size_of::<PackedWall>();
+    let string_table = thing_table + level.things.len() * size_of::<PackedThing>();
+    let strings_len: usize = names.iter().map(|name| name.len() + 1).sum();
+
+    out.write_all(&MAGIC)?;
+    for field in [
+        VERSION,
+        to_u32(sectors.len(), "sector count")?,
+        to_u32(walls.len(), "wall count")?,
+        to_u32(sector_table, "sector table offset")?,
+        to_u32(wall_table, "wall table offset")?,
+        to_u32(level.things.len(), "thing count")?,
+        to_u32(thing_table, "thing table offset")?,
+        to_u32(string_table, "string table offset")?,
+        to_u32(strings_len, "string table size")?,
+        to_u32(level.textures.len(), "texture count")?,
+        flags,
+        // the title is the last string, if there is one
+        to_u32(names.len().saturating_sub(1), "title")?,
+        player.pos.x.to_bits(),
+        player.pos.y.to_bits(),
+        player.angle.to_bits(),
+        to_u32(player.sector.unwrap_or(0), "player sector")?,
+    ] {
+        out.write_all(&field.to_le_bytes())?;
+    }
+
+    for sector in sectors {
+        out.write_all(&sector.id.to_le_bytes())?;
+        out.write_all(&to_u32(sector.firstwall, "firstwall")?.to_le_bytes())?;
+        out.write_all(&to_u32(sector.nwalls, "nwalls")?.to_le_bytes())?;
+        out.write_all(&sector.zfloor.to_le_bytes())?;
+        out.write_all(&sector.zceil.to_le_bytes())?;
+        out.write_all(&to_u32(sector.floor, "floor texture")?.to_le_bytes())?;
+        out.write_all(&to_u32(sector.ceil, "ceiling texture")?.to_le_bytes())?;
+        out.write_all(&sector.light.to_le_bytes())?;
+        let mut flags = if sector.sky { SECTOR_SKY } else { 0 };
+        if sector.floor_slope.is_some() {
+            flags |= SECTOR_FLOOR_SLOPE;
+        }
+        if sector.ceil_slope.is_some() {
+            flags |= SECTOR_CEIL_SLOPE;
+        }
+        out.write_all(&flags.to_le_bytes())?;
+
+        for slope in [&sector.floor_slope, &sector.ceil_slope] {
+            let (wall, delta) = slope
+                .as_ref()
+                .map_or((0, 0.0), |slope| (slope.wall, slope.delta));
+            out.write_all(&to_u32(wall, "slope wall")?.to_le_bytes())?;
+            out.write_all(&delta.to_le_bytes())?;
+        }
+    }
+
+    for wall in walls {
+        for coord in [wall.a.x, wall.a.y, wall.b.x, wall.b.y] {
+            out.write_all(&coord.to_le_bytes())?;
+        }
+        out.write_all(&to_u32(wall.portal, "portal")?.to_le_bytes())?;
+        out.write_all(&to_u32(wall.texture, "texture")?.to_le_bytes())?;
+        out.write_all(&to_u32(wall.upper, "upper texture")?.to_le_bytes())?;
+        out.write_all(&to_u32(wall.lower, "lower texture")?.to_le_bytes())?;
+        let mut flags = if wall.transform.is_some() {
+            WALL_TRANSFORM
+        } else {
+            0
+        };
+        if wall.mirror {
+            flags |= WALL_MIRROR;
+        }
+        out.write_all(&flags.to_le_bytes())?;
+
+        let (offset, angle) = wall
+            .transform
+            .as_ref()
+            .map_or((V2::default(), 0.0), |t| (t.offset.clone(), t.angle));
+        for field in [offset.x, offset.y, angle] {
+            out.write_all(&field.to_le_bytes())?;
+        }
+    }
+
+    for (i, thing) in level.things.iter().enumerate() {
+        let name = level.textures.len() + i;
+        out.write_all(&to_u32(name, "thing name")?.to_le_bytes())?;
+        out.write_all(&thing.pos.x.to_le_bytes())?;
+        out.write_all(&thing.pos.y.to_le_bytes())?;
+        out.write_all(&to_u32(thing.sprite, "sprite")?.to_le_bytes())?;
+        out.write_all(&thing.height.to_le_bytes())?;
+    }
+
+    for name in &names {
+        if name.contains('\0') {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("name {:?} contains NUL", name),
+            ));
+        }
+        out.write_all(name.as_bytes())?;
+        out.write_all(&[0])?;
+    }
+
+    out.flush()
+}
+
+/// Compiles a level in any format `read_level` takes into the binary format,
+/// linking its portals first as `link` says, if at all.
+pub fn compile_level(src: &str, dst: &str, link: Option<LinkMode>) -> Result<(), LevelError> {
+    let level = match link {
+        Some(mode) => read_level_with(src, mode)?,
+        None => read_level(src)?,
+    };
+    write_binary(dst, &level).map_err(|error| LevelError::open(dst, error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::res::level::text::read_text;
+    use crate::res::testing::{root, TempFile};
+
+    fn level() -> Level {
+        let source = root().join("level.txt");
+        read_text(source.to_str().unwrap()).unwrap()
+    }
+
+    /// `level.txt` compiled, to be broken in various ways.
+    fn compiled() -> Vec<u8> {
+        let file = TempFile::new("compiled.zlb");
+        write_binary(file.path(), &level()).unwrap();
+        std::fs::read(file.path()).unwrap()
+    }
+
+    fn corruption(bytes: &[u8]) -> String {
+        let file = TempFile::new("corrupt.zlb");
+        std::fs::write(file.path(), bytes).unwrap();
+        match read_binary(file.path()) {
+            Err(LevelError::Corrupt { message, .. }) => message,
+            other => panic!("expected a corrupt level, got {:?}", other),
+        }
+    }
+
+    /// Overwrites the header field at `offset` bytes.
+    fn patch(bytes: &mut [u8], offset: usize, value: u32) {
+        bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    #[test]
+    fn compile_round_trip() {
+        let mut paths = vec![root().join("level.txt")];
+        for entry in std::fs::read_dir(root().join("tests/levels")).unwrap() {
+            paths.push(entry.unwrap().path());
+        }
+
+        for path in paths {
+            let path = path.to_str().unwrap();
+            let file = TempFile::new("round-trip.zlb");
+            compile_level(path, file.path(), None).unwrap();
+            assert_eq!(
+                read_binary(file.path()).unwrap(),
+                read_level(path).unwrap(),
+                "{} changed when compiled",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn too_short() {
+        assert_eq!(
+            corruption(&compiled()[..10]),
+            "header runs past the end of the file"
+        );
+
+        // level.txt has no strings, the walls come last
+        let mut bytes = compiled();
+        bytes.truncate(bytes.len() - 1);
+        assert_eq!(
+            corruption(&bytes),
+            "wall table runs past the end of the file"
+        );
+    }
+
+    #[test]
+    fn wrong_version() {
+        let mut bytes = compiled();
+        patch(&mut bytes, 4, VERSION - 1);
+        assert_eq!(
+            corruption(&bytes),
+            format!(
+                "level version {} is not supported, expected {}",
+                VERSION - 1,
+                VERSION
+            )
+        );
+
+        bytes[..4].copy_from_slice(b"ZLVX");
+        assert_eq!(corruption(&bytes), "not a compiled level, bad magic");
+    }
+
+    #[test]
+    fn offsets_out_of_range() {
+        // the wall table's offset, then its length
+        let mut bytes = compiled();
+        patch(&mut bytes, 20, u32::MAX);
+        assert_eq!(
+            corruption(&bytes),
+            "wall table runs past the end of the file"
+        );
+
+        let mut bytes = compiled();
+        patch(&mut bytes, 12, u32::MAX);
+        assert_eq!(
+            corruption(&bytes),
+            "wall table runs past the end of the file"
+        );
+
+        // the sector table's offset
+        let mut bytes = compiled();
+        patch(&mut bytes, 16, 1);
+        assert_eq!(corruption(&bytes), "sector table is misaligned");
+
+        // the title's index into the string table
+        let mut level = level();
+        level.title = Some("The Cellar".to_string());
+        let file = TempFile::new("title.zlb");
+        write_binary(file.path(), &level).unwrap();
+        let mut bytes = std::fs::read(file.path()).unwrap();
+        patch(&mut bytes, 48, 1000);
+        assert_eq!(corruption(&bytes), "title is out of range");
+    }
+
+    #[test]
+    fn player_and_title_round_trip() {
+        let mut level = level();
+        level.title = Some("The Cellar".to_string());
+        level.player = Some(PlayerStart {
+            pos: V2::new(2.5, 3.25),
+            angle: 1.5,
+            sector: Some(2),
+        });
+
+        let file = TempFile::new("player-title.zlb");
+        write_binary(file.path(), &level).unwrap();
+        let compiled = read_binary(file.path()).unwrap();
+        assert_eq!(compiled.title, level.title);
+        assert_eq!(compiled.player, level.player);
+
+        level.title = None;
+        level.player.as_mut().unwrap().sector = None;
+        write_binary(file.path(), &level).unwrap();
+        let compiled = read_binary(file.path()).unwrap();
+        assert_eq!(compiled.title, None);
+        assert_eq!(compiled.player, level.player);
+    }
+}
@@ -0,0 +1,351 @@
+use std::collections::HashMap;
+
+use crate::res::util::kinds::V2i;
+
+/// A corner of a polygon along with what the edge from it to the next corner
+/// carries, `None` for the edges added to split the polygon up.
+pub type Corner<T> = (V2i, Option<T>);
+
+/// Twice the signed area of `a`, `b`, `c`: negative if they turn right,
+/// which is the way sector walls go round, and 0 if they are in line.
+fn cross(a: &V2i, b: &V2i, c: &V2i) -> i64 {
+    let (abx, aby) = ((b.x - a.x) as i64, (b.y - a.y) as i64);
+    let (acx, acy) = ((c.x - a.x) as i64, (c.y - a.y) as i64);
+    abx * acy - aby * acx
+}
+
+/// Twice the signed area of a polygon, negative if it winds clockwise.
+fn area<T>(polygon: &[Corner<T>]) -> i64 {
+    (0..polygon.len())
+        .map(|i| {
+            let (a, b) = (&polygon[i].0, &polygon[(i + 1) % polygon.len()].0);
+            a.x as i64 * b.y as i64 - b.x as i64 * a.y as i64
+        })
+        .sum()
+}
+
+/// Whether `p` is inside `polygon`, by counting the edges a ray to +x
+/// crosses.
+fn contains<T>(polygon: &[Corner<T>], p: &V2i) -> bool {
+    let mut inside = false;
+    for i in 0..polygon.len() {
+        let (a, b) = (&polygon[i].0, &polygon[(i + 1) % polygon.len()].0);
+        if (a.y > p.y) != (b.y > p.y) {
+            // where the edge crosses p's row, compared without dividing
+            let t = cross(a, b, p);
+            if (t > 0) == (b.y > a.y) {
+                inside = !inside;
+            }
+        }
+    }
+    inside
+}
+
+/// Whether `polygon` is convex and clockwise, with no corner twice. Corners
+/// in line with their neighbours are fine.
+fn is_convex<T>(polygon: &[Corner<T>]) -> bool {
+    let n = polygon.len();
+    let turns_right = (0..n).all(|i| {
+        let (a, b, c) = (
+            &polygon[i].0,
+            &polygon[(i + 1) % n].0,
+            &polygon[(i + 2) % n].0,
+        );
+        cross(a, b, c) <= 0
+    });
+    let distinct = (0..n).all(|i| !polygon[i + 1..].iter().any(|c| c.0 == polygon[i].0));
+    n >= 3 && turns_right && distinct && area(polygon) < 0
+}
+
+/// Whether the segments `p0`-`p1` and `q0`-`q1` cross or touch.
+fn segments_meet(p0: &V2i, p1: &V2i, q0: &V2i, q1: &V2i) -> bool {
+    let on = |a: &V2i, b: &V2i, c: &V2i| {
+        a.x.min(b.x) <= c.x && c.x <= a.x.max(b.x) && a.y.min(b.y) <= c.y && c.y <= a.y.max(b.y)
+    };
+    let (d0, d1) = (cross(p0, p1, q0), cross(p0, p1, q1));
+    let (d2, d3) = (cross(q0, q1, p0), cross(q0, q1, p1));
+
+    (d0.signum() * d1.signum() < 0 && d2.signum() * d3.signum() < 0)
+        || (d0 == 0 && on(p0, p1, q0))
+        || (d1 == 0 && on(p0, p1, q1))
+        || (d2 == 0 && on(q0, q1, p0))
+        || (d3 == 0 && on(q0, q1, p1))
+}
+
+/// Whether the segment from corner `i` of `polygon` to `p` starts off into
+/// the area the polygon bounds, which is to the right of its edges.
+fn in_cone<T>(polygon: &[Corner<T>], i: usize, p: &V2i) -> bool {
+    let n = polygon.len();
+    let (prev, at, next) = (
+        &polygon[(i + n - 1) % n].0,
+        &polygon[i].0,
+        &polygon[(i + 1) % n].0,
+    );
+    if cross(prev, at, next) <= 0 {
+        cross(prev, at, p) < 0 && cross(at, next, p) < 0
+    } else {
+        cross(prev, at, p) < 0 || cross(at, next, p) < 0
+    }
+}
+
+/// Joins `hole` to `polygon` by a pair of edges from the hole's rightmost
+/// corner to the nearest corner of `polygon` it can see, so the two make one
+/// loop. `others` are the holes still to join, which the bridge must not
+/// cross either.
+fn bridge<T: Clone>(
+    polygon: &mut Vec<Corner<T>>,
+    hole: &[Corner<T>],
+    others: &[Vec<Corner<T>>],
+) -> Option<()> {
+    let m = (0..hole.len()).max_by_key(|&i| (hole[i].0.x, hole[i].0.y))?;
+    let from = &hole[m].0;
+
+    let edges = || {
+        [&polygon[..], hole]
+            .into_iter()
+            .chain(others.iter().map(|other| &other[..]))
+            .flat_map(|loop_| {
+                (0..loop_.len()).map(move |i| (&loop_[i].0, &loop_[(i + 1) % loop_.len()].0))
+            })
+    };
+    let visible = |to: &V2i| {
+        edges()
+            .filter(|(a, b)| *a != from && *b != from && *a != to && *b != to)
+            .all(|(a, b)| !segments_meet(from, to, a, b))
+    };
+
+    let dist = |p: &V2i| {
+        let (dx, dy) = ((p.x - from.x) as i64, (p.y - from.y) as i64);
+        dx * dx + dy * dy
+    };
+    let p = (0..polygon.len())
+        .filter(|&i| polygon[i].0 != *from)
+        .filter(|&i| in_cone(polygon, i, from) && in_cone(hole, m, &polygon[i].0))
+        .filter(|&i| visible(&polygon[i].0))
+        .min_by_key(|&i| dist(&polygon[i].0))?;
+
+    // ..., P -> M, round the hole back to M, M -> P, P -> on as before
+    let mut joined = Vec::with_capacity(polygon.len() + hole.len() + 2);
+    joined.extend_from_slice(&polygon[..p]);
+    joined.push((polygon[p].0.clone(), None));
+    joined.extend((0..hole.len()).map(|k| hole[(m + k) % hole.len()].clone()));
+    joined.push((from.clone(), None));
+    joined.extend_from_slice(&polygon[p..]);
+    *polygon = joined;
+    Some(())
+}
+
+/// Cuts a clockwise simple polygon into triangles, one ear at a time.
+fn triangulate<T: Clone>(mut polygon: Vec<Corner<T>>) -> Option<Vec<Vec<Corner<T>>>> {
+    let mut triangles = Vec::with_capacity(polygon.len().saturating_sub(2));
+
+    while polygon.len() > 3 {
+        let n = polygon.len();
+        let ear = (0..n).find(|&i| {
+            let (a, b, c) = (
+                &polygon[(i + n - 1) % n].0,
+                &polygon[i].0,
+                &polygon[(i + 1) % n].0,
+            );
+            cross(a, b, c) < 0
+                && polygon.iter().all(|(p, _)| {
+                    p == a
+                        || p == b
+                        || p == c
+                        || cross(a, b, p) > 0
+                        || cross(b, c, p) > 0
+                        || cross(c, a, p) > 0
+                })
+        })?;
+
+        let prev = (ear + n - 1) % n;
+        triangles.push(vec![
+            polygon[prev].clone(),
+            polygon[ear].clone(),
+            (polygon[(ear + 1) % n].0.clone(), None),
+        ]);
+        polygon[prev].1 = None;
+        polygon.remove(ear);
+    }
+
+    if area(&polygon) >= 0 {
+        return None;
+    }
+    triangles.push(polygon);
+    Some(triangles)
+}
+
+/// Merges pieces across the edges added between them wherever what comes
+/// out is still convex.
+fn merge<T: Clone>(mut pieces: Vec<Vec<Corner<T>>>) -> Vec<Vec<Corner<T>>> {
+    loop {
+        let mut added: HashMap<(V2i, V2i), (usize, usize)> = HashMap::new();
+        for (i, piece) in pieces.iter().enumerate() {
+            for (k, (a, carries)) in piece.iter().enumerate() {
+                if carries.is_none() {
+                    let b = &piece[(k + 1) % piece.len()].0;
+                    added.insert((a.clone(), b.clone()), (i, k));
+                }
+            }
+        }
+
+        // pieces merged this time round have moved on from `added`
+        let mut merged = false;
+        let mut touched = vec![false; pieces.len()];
+        let mut gone = vec![false; pieces.len()];
+        for i in 0..pieces.len() {
+            for k in 0..pieces[i].len() {
+                if touched[i] || pieces[i][k].1.is_some() {
+                    continue;
+                }
+                let (a, b) = (&pieces[i][k].0, &pieces[i][(k + 1) % pieces[i].len()].0);
+                let Some(&(j, l)) = added.get(&(b.clone(), a.clone())) else {
+                    continue;
+                };
+                if j == i || touched[j] {
+                    continue;
+                }
+
+                // piece i from b round to a, then piece j from a round to b
+                let (p, q) = (&pieces[i], &pieces[j]);
+                let joined: Vec<Corner<T>> = (1..p.len())
+                    .map(|s| p[(k + s) % p.len()].clone())
+                    .chain((1..q.len()).map(|s| q[(l + s) % q.len()].clone()))
+                    .collect();
+                if is_convex(&joined) {
+                    pieces[i] = joined;
+                    touched[i] = true;
+                    touched[j] = true;
+                    gone[j] = true;
+                    merged = true;
+                }
+            }
+        }
+
+        pieces = pieces
+            .into_iter()
+            .zip(gone)
+            .filter_map(|(piece, gone)| (!gone).then_some(piece))
+            .collect();
+        if !merged {
+            return pieces;
+        }
+    }
+}
+
+/// Splits the area `loops` bound into convex, clockwise pieces. Loops go
+/// round the area clockwise and round holes in it anticlockwise, keeping the
+/// area on their right as sector walls do. Every corner keeps what it
+/// carried, and the edges added between pieces carry `None`, each one
+/// matched by the same edge the other way round in the piece next to it.
+/// Returns `None` if the loops cross or are not laid out that way.
+pub fn convex_pieces<T: Clone>(loops: Vec<Vec<Corner<T>>>) -> Option<Vec<Vec<Corner<T>>>> {
+    if let [single] = &loops[..] {
+        if is_convex(single) {
+            return Some(loops);
+        }
+    }
+
+    if loops.iter().any(|loop_| area(loop_) == 0) {
+        return None;
+    }
+    let (mut outers, holes): (Vec<_>, Vec<_>) =
+        loops.into_iter().partition(|loop_| area(loop_) < 0);
+
+    // each hole belongs to the smallest outer loop around it
+    let mut inside: Vec<Vec<Vec<Corner<T>>>> = outers.iter().map(|_| vec![]).collect();
+    for hole in holes {
+        let around = (0..outers.len())
+            .filter(|&i| contains(&outers[i], &hole[0].0))
+            .min_by_key(|&i| -area(&outers[i]))?;
+        inside[around].push(hole);
+    }
+
+    let mut pieces = vec![];
+    for (outer, mut holes) in outers.iter_mut().zip(inside) {
+        // rightmost first, so each bridge heads for the outer loop or a
+        // hole already joined to it
+        holes.sort_by_key(|hole| std::cmp::Reverse(hole.iter().map(|c| c.0.x).max()));
+        while !holes.is_empty() {
+            let hole = holes.remove(0);
+            bridge(outer, &hole, &holes)?;
+        }
+        pieces.extend(triangulate(std::mem::take(outer))?);
+    }
+
+    Some(merge(pieces))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn polygon(points: &[(i32, i32)]) -> Vec<Corner<usize>> {
+        points
+            .iter()
+            .enumerate()
+            .map(|(i, &(x, y))| (V2i::new(x, y), Some(i)))
+            .collect()
+    }
+
+    /// Checks `pieces` are convex, cover `area2` and keep every edge of the
+    /// loops they came from, and that every added edge has a twin.
+    fn check(pieces: &[Vec<Corner<usize>>], area2: i64, edges: usize) {
+        assert!(pieces.iter().all(|piece| is_convex(piece)));
+        assert_eq!(pieces.iter().map(|piece| area(piece)).sum::<i64>(), area2);
+
+        let mut kept: Vec<_> = pieces.iter().flatten().filter_map(|c| c.1).collect();
+        kept.sort();
+        kept.dedup();
+        assert_eq!(kept.len(), edges);
+
+        let added: Vec<_> = pieces
+            .iter()
+            .flat_map(|piece| {
+                (0..piece.len())
+                    .filter(|&k| piece[k].1.is_none())
+                    .map(|k| (piece[k].0.clone(), piece[(k + 1) % piece.len()].0.clone()))
+            })
+            .collect();
+        for (a, b) in &added {
+            assert!(
+                added.contains(&(b.clone(), a.clone())),
+                "no twin for {:?}",
+                (a, b)
+            );
+        }
+    }
+
+    #[test]
+    fn convex_loops_are_kept() {
+        let square = polygon(&[(0, 0), (0, 4), (4, 4), (4, 0)]);
+        assert_eq!(convex_pieces(vec![square.clone()]), Some(vec![square]));
+    }
+
+    #[test]
+    fn concave_loops_are_split() {
+        // an L, with a corner in line with its neighbours along the bottom
+        let l = polygon(&[(0, 0), (0, 8), (4, 8), (4, 4), (8, 4), (8, 0), (4, 0)]);
+        let pieces = convex_pieces(vec![l]).unwrap();
+        assert_eq!(pieces.len(), 2);
+        check(&pieces, -2 * 48, 7);
+    }
+
+    #[test]
+    fn holes_are_bridged() {
+        // a square room around a square pillar
+        let room = polygon(&[(0, 0), (0, 12), (12, 12), (12, 0)]);
+        let mut pillar = polygon(&[(4, 4), (8, 4), (8, 8), (4, 8)]);
+        for (k, corner) in pillar.iter_mut().enumerate() {
+            corner.1 = Some(4 + k);
+        }
+        let pieces = convex_pieces(vec![room, pillar]).unwrap();
+        check(&pieces, -2 * (144 - 16), 8);
+    }
+
+    #[test]
+    fn crossed_loops_are_rejected() {
+        let bow = polygon(&[(0, 0), (4, 4), (4, 0), (0, 4)]);
+        assert_eq!(convex_pieces(vec![bow]), None);
+    }
+}
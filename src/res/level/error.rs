@@ -0,0 +1,329 @@
+use std::fmt;
+use std::io;
+
+use super::validate::LevelProblem;
+
+/// Points at the offending token of a level file line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    pub path: String,
+    /// 1-based line number
+    pub line: usize,
+    /// 1-based column (in chars) of the offending token
+    pub column: usize,
+    /// the offending token, empty if the line ended early
+    pub token: String,
+    /// the whole source line, used for the snippet
+    pub source: String,
+}
+
+#[derive(Debug)]
+pub enum LevelError {
+    /// the file could not be opened, read or written
+    Io {
+        path: String,
+        line: Option<usize>,
+        error: io::Error,
+    },
+    /// an unknown `[SECTION]` header, or data outside of any section
+    UnknownSection { at: Location, message: String },
+    /// a line has the wrong number of fields for its section
+    FieldCount {
+        at: Location,
+        section: &'static str,
+        expected: usize,
+        found: usize,
+    },
+    /// a compiled level or WAD is truncated, inconsistent or of another version
+    Corrupt { path: String, message: String },
+    /// a WAD map has a sector that can't be split into convex pieces
+    Unsupported { path: String, message: String },
+    /// a structured level does not parse, or a field is not one of the
+    /// words it can be
+    Syntax { at: Location, message: String },
+    /// the level refers to a sector that doesn't exist, names two sectors
+    /// the same, or starts the player or a thing outside of every sector
+    BadReference { path: String, message: String },
+    /// a field is not a number of the expected kind
+    BadNumber {
+        at: Location,
+        expected: &'static str,
+    },
+}
+
+impl Location {
+    pub fn new(path: &str, line: usize, source: &str, column: usize, token: &str) -> Self {
+        Location {
+            path: path.to_string(),
+            line,
+            column,
+            token: token.to_string(),
+            source: source.to_string(),
+        }
+    }
+}
+
+impl LevelError {
+    pub fn open(path: &str, error: io::Error) -> Self {
+        LevelError::Io {
+            path: path.to_string(),
+            line: None,
+            error,
+        }
+    }
+
+    pub fn read(path: &str, line: usize, error: io::Error) -> Self {
+        LevelError::Io {
+            path: path.to_string(),
+            line: Some(line),
+            error,
+        }
+    }
+
+    pub fn location(&self) -> Option<&Location> {
+        match self {
+            LevelError::Io { .. }
+            | LevelError::Corrupt { .. }
+            | LevelError::Unsupported { .. }
+            | LevelError::BadReference { .. } => None,
+            LevelError::UnknownSection { at, .. }
+            | LevelError::Syntax { at, .. }
+            | LevelError::FieldCount { at, .. }
+            | LevelError::BadNumber { at, .. } => Some(at),
+        }
+    }
+
+    /// Human-readable description, without the location or snippet.
+    pub fn message(&self) -> String {
+        match self {
+            LevelError::Io { error, .. } => error.to_string(),
+            LevelError::UnknownSection { message, .. }
+            | LevelError::Corrupt { message, .. }
+            | LevelError::Unsupported { message, .. }
+            | LevelError::Syntax { message, .. }
+            | LevelError::BadReference { message, .. } => message.clone(),
+            LevelError::FieldCount {
+                section,
+                expected,
+                found,
+                ..
+            } => format!(
+                "expected {} fields in [{}], found {}",
+                expected, section, found
+            ),
+            LevelError::BadNumber { at, expected } => {
+                format!("`{}` is not {}", at.token, expected)
+            }
+        }
+    }
+}
+
+impl fmt::Display for LevelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let at = match self {
+            LevelError::Io {
+                path,
+                line: Some(line),
+                ..
+            } => return write!(f, "error: {}:{}: {}", path, line, self.message()),
+            LevelError::Io { path, .. }
+            | LevelError::Corrupt { path, .. }
+            | LevelError::Unsupported { path, .. }
+            | LevelError::BadReference { path, .. } => {
+                return write!(f, "error: {}: {}", path, self.message())
+            }
+            _ => self.location().unwrap(),
+        };
+
+        writeln!(f, "error: {}", self.message())?;
+        writeln!(f, " --> {}:{}:{}", at.path, at.line, at.column)?;
+
+        // keep tabs in the caret line so it lines up with the source, and
+        // carry on with spaces for a column past the end of the line
+        let gutter = " ".repeat(at.line.to_string().len());
+        let pad: String = at
+            .source
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .chain(std::iter::repeat(' '))
+            .take(at.column.saturating_sub(1))
+            .collect();
+        let carets = "^".repeat(usize::max(at.token.chars().count(), 1));
+
+        writeln!(f, "{} |", gutter)?;
+        writeln!(f, "{} | {}", at.line, at.source)?;
+        write!(f, "{} | {}{}", gutter, pad, carets)
+    }
+}
+
+impl std::error::Error for LevelError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LevelError::Io { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// Why a level could not be loaded, or a reload kept the level that was
+/// already loaded.
+#[derive(Debug)]
+pub enum ReloadError {
+    Load(LevelError),
+    Invalid(Vec<LevelProblem>),
+    /// the camera is outside of the new level and its old sector is gone
+    CameraLost,
+}
+
+impl fmt::Display for ReloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReloadError::Load(error) => write!(f, "{}", error),
+            ReloadError::Invalid(problems) => {
+                write!(f, "error: level has {} problem(s):", problems.len())?;
+                for problem in problems {
+                    write!(f, "\n  {}", problem)?;
+                }
+                Ok(())
+            }
+            ReloadError::CameraLost => write!(
+                f,
+                "error: the camera is outside of the reloaded level and its sector is gone"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ReloadError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn missing() -> io::Error {
+        io::Error::new(io::ErrorKind::NotFound, "no such file")
+    }
+
+    #[test]
+    fn io() {
+        let error = LevelError::open("level.txt", missing());
+        assert_eq!(error.to_string(), "error: level.txt: no such file");
+
+        let error = LevelError::read("level.txt", 12, missing());
+        assert_eq!(error.to_string(), "error: level.txt:12: no such file");
+    }
+
+    #[test]
+    fn unknown_section() {
+        let error = LevelError::UnknownSection {
+            at: Location::new("level.txt", 3, "[SECTORS]", 1, "[SECTORS]"),
+            message: "unknown section [SECTORS]".to_string(),
+        };
+        assert_eq!(
+            error.to_string(),
+            "error: unknown section [SECTORS]\n \
+             --> level.txt:3:1\n  \
+             |\n\
+             3 | [SECTORS]\n  \
+             | ^^^^^^^^^"
+        );
+    }
+
+    #[test]
+    fn field_count_past_the_end_of_the_line() {
+        // the missing field is pointed at one past the last one, and further
+        // out the caret keeps going instead of stopping at the end
+        let error = LevelError::FieldCount {
+            at: Location::new("level.txt", 10, "1 0 4 2", 9, ""),
+            section: "SECTOR",
+            expected: 6,
+            found: 4,
+        };
+        assert_eq!(
+            error.to_string(),
+            "error: expected 6 fields in [SECTOR], found 4\n \
+             --> level.txt:10:9\n   \
+             |\n\
+             10 | 1 0 4 2\n   \
+             |         ^"
+        );
+
+        let error = LevelError::FieldCount {
+            at: Location::new("level.txt", 10, "1 0", 7, ""),
+            section: "SECTOR",
+            expected: 6,
+            found: 2,
+        };
+        assert!(error.to_string().ends_with("\n   |       ^"));
+    }
+
+    #[test]
+    fn corrupt() {
+        let error = LevelError::Corrupt {
+            path: "level.zlb".to_string(),
+            message: "wall table runs past the end of the file".to_string(),
+        };
+        assert_eq!(
+            error.to_string(),
+            "error: level.zlb: wall table runs past the end of the file"
+        );
+    }
+
+    #[test]
+    fn unsupported() {
+        let error = LevelError::Unsupported {
+            path: "doom.wad".to_string(),
+            message: "map E1M1 sector 4 can't be split into convex pieces, its linedefs cross"
+                .to_string(),
+        };
+        assert_eq!(
+            error.to_string(),
+            "error: doom.wad: map E1M1 sector 4 can't be split into convex pieces, \
+             its linedefs cross"
+        );
+    }
+
+    #[test]
+    fn syntax_keeps_tabs() {
+        let error = LevelError::Syntax {
+            at: Location::new("level.ron", 7, "\t\tzceil 4.0,", 9, "4.0"),
+            message: "expected `:`".to_string(),
+        };
+        assert_eq!(
+            error.to_string(),
+            "error: expected `:`\n \
+             --> level.ron:7:9\n  \
+             |\n\
+             7 | \t\tzceil 4.0,\n  \
+             | \t\t      ^^^"
+        );
+    }
+
+    #[test]
+    fn bad_reference() {
+        let error = LevelError::BadReference {
+            path: "level.ron".to_string(),
+            message: "sector name \"hall\" is used twice".to_string(),
+        };
+        assert_eq!(
+            error.to_string(),
+            "error: level.ron: sector name \"hall\" is used twice"
+        );
+    }
+
+    #[test]
+    fn bad_number() {
+        let error = LevelError::BadNumber {
+            at: Location::new("level.txt", 4, "2 0 1.5 4", 5, "1.5"),
+            expected: "an integer",
+        };
+        assert_eq!(
+            error.to_string(),
+            "error: `1.5` is not an integer\n \
+             --> level.txt:4:5\n  \
+             |\n\
+             4 | 2 0 1.5 4\n  \
+             |     ^^^"
+        );
+    }
+}
@@ -0,0 +1,103 @@
+pub mod binary;
+pub mod convex;
+pub mod error;
+pub mod portals;
+pub mod save;
+pub mod structured;
+pub mod text;
+pub mod validate;
+pub mod wad;
+pub mod watch;
+
+use std::path::Path;
+
+use self::error::LevelError;
+use self::portals::{link_portals, LinkMode};
+use crate::internaltypes::thing::Thing;
+use crate::res::util::constants::{EYE_Z, MIRROR_TEXTURE, SKY_TEXTURE, TEXTURE_NON};
+use crate::res::util::kinds::{Sectors, Walls, V2};
+
+/// Where the camera starts out.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PlayerStart {
+    pub pos: V2,
+    /// radians
+    pub angle: f32,
+    /// the sector `pos` is in, if the level says so
+    pub sector: Option<usize>,
+}
+
+/// Everything read from a level file.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Level {
+    pub sectors: Sectors,
+    pub walls: Walls,
+
+    pub title: Option<String>,
+    pub player: Option<PlayerStart>,
+    /// with their sectors still to be found
+    pub things: Vec<Thing>,
+
+    /// names of the textures the sectors, walls and things use, texture `i`
+    /// being `textures[i - 1]` until the level is bound to a texture registry
+    pub textures: Vec<String>,
+}
+
+impl Level {
+    /// The texture reference for `name`, adding it to `textures` the
+    /// first time it is used.
+    pub fn texture_ref(&mut self, name: &str) -> usize {
+        match self.textures.iter().position(|t| t == name) {
+            Some(i) => i + 1,
+            None => {
+                self.textures.push(name.to_string());
+                self.textures.len()
+            }
+        }
+    }
+
+    /// The texture reference and sky flag for a ceiling called `name`. A
+    /// ceiling called `SKY_TEXTURE` is open to the sky and has no texture.
+    pub fn ceiling_ref(&mut self, name: &str) -> (usize, bool) {
+        match name {
+            SKY_TEXTURE => (TEXTURE_NON, true),
+            name => (self.texture_ref(name), false),
+        }
+    }
+
+    /// The texture reference and mirror flag for a wall textured `name`. A
+    /// wall textured `MIRROR_TEXTURE` is a mirror and has no texture.
+    pub fn wall_ref(&mut self, name: &str) -> (usize, bool) {
+        match name {
+            MIRROR_TEXTURE => (TEXTURE_NON, true),
+            name => (self.texture_ref(name), false),
+        }
+    }
+}
+
+/// Reads a level, picking the format from the file extension: `.zlb` is the
+/// compiled binary format, `.ron` the structured format, `.wad` imports the
+/// first map of a Doom WAD, and anything else is the `[SECTOR]`/`[WALL]` text
+/// format.
+pub fn read_level(path: &str) -> Result<Level, LevelError> {
+    let ext = Path::new(path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase());
+
+    match ext.as_deref() {
+        Some(binary::EXTENSION) => binary::read_binary(path),
+        Some(structured::EXTENSION) => structured::read_structured(path),
+        Some(wad::EXTENSION) => wad::import_wad(path, None, EYE_Z / wad::DOOM_EYE_Z),
+        _ => text::read_text(path),
+    }
+}
+
+/// As `read_level`, then links the walls that coincide with a reversed wall
+/// of another sector as `mode` says, so the level only needs its geometry
+/// drawn.
+pub fn read_level_with(path: &str, mode: LinkMode) -> Result<Level, LevelError> {
+    let mut level = read_level(path)?;
+    link_portals(&level.sectors, &mut level.walls, mode);
+    Ok(level)
+}
@@ -0,0 +1,440 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+
+use crate::res::util::constants::{SECTOR_NON, TEXTURE_NON};
+use crate::res::util::kinds::{PortalTransform, Sectors, V2i, Wall, Walls};
+
+/// A portal that is not mirrored properly by the sector it leads into.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PortalProblem {
+    /// the target sector has no wall leading back at all
+    OneWay {
+        sector: usize,
+        wall: usize,
+        target: usize,
+    },
+    /// the target sector has the reversed wall, but it is solid (`SECTOR_NON`)
+    NoBackLink {
+        sector: usize,
+        wall: usize,
+        target: usize,
+        back: usize,
+    },
+    /// the reversed wall in the target sector leads somewhere else
+    WrongBackLink {
+        sector: usize,
+        wall: usize,
+        target: usize,
+        back: usize,
+        back_portal: usize,
+    },
+    /// the target sector links back, but not from this wall's reversed endpoints
+    MismatchedEndpoints {
+        sector: usize,
+        wall: usize,
+        target: usize,
+        back: usize,
+    },
+    /// the wall leading back does not undo this wall's transform
+    MismatchedTransform {
+        sector: usize,
+        wall: usize,
+        target: usize,
+        back: usize,
+    },
+}
+
+/// How `link_portals` treats the portals already in the level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkMode {
+    /// only link solid walls that have a reversed twin in another sector
+    Fill,
+    /// derive every portal from geometry, making walls without a twin solid
+    Rebuild,
+}
+
+impl fmt::Display for PortalProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PortalProblem::OneWay {
+                sector,
+                wall,
+                target,
+            } => write!(
+                f,
+                "sector {}, wall {}: one-way portal, sector {} has no wall leading back",
+                sector, wall, target
+            ),
+            PortalProblem::NoBackLink {
+                sector,
+                wall,
+                target,
+                back,
+            } => write!(
+                f,
+                "sector {}, wall {}: sector {} wall {} is the other side but is solid",
+                sector, wall, target, back
+            ),
+            PortalProblem::WrongBackLink {
+                sector,
+                wall,
+                target,
+                back,
+                back_portal,
+            } => write!(
+                f,
+                "sector {}, wall {}: sector {} wall {} is the other side but leads to sector {}",
+                sector, wall, target, back, back_portal
+            ),
+            PortalProblem::MismatchedEndpoints {
+                sector,
+                wall,
+                target,
+                back,
+            } => write!(
+                f,
+                "sector {}, wall {}: sector {} links back from wall {}, whose endpoints do not match",
+                sector, wall, target, back
+            ),
+            PortalProblem::MismatchedTransform {
+                sector,
+                wall,
+                target,
+                back,
+            } => write!(
+                f,
+                "sector {}, wall {}: sector {} wall {} is the other side but does not transform back",
+                sector, wall, target, back
+            ),
+        }
+    }
+}
+
+fn wall_range(sectors: &Sectors, walls: &Walls, sector: usize) -> Range<usize> {
+    let s = &sectors.arr[sector];
+    let first = usize::min(s.firstwall, walls.len());
+    let end = s.firstwall.saturating_add(s.nwalls);
+    first..usize::min(end, walls.len())
+}
+
+/// Where `p` is carried through a portal with `transform`, snapped back to
+/// the grid walls are on.
+fn carry(transform: &Option<PortalTransform>, p: &V2i) -> V2i {
+    match transform {
+        Some(transform) => {
+            let p = transform.apply(&p.clone().v2i_to_v2());
+            V2i::new(p.x.round() as i32, p.y.round() as i32)
+        }
+        None => p.clone(),
+    }
+}
+
+/// Whether `back` carries its endpoints back to those of `wall`, as it must to
+/// undo whatever transform `wall` has.
+fn carries_back(wall: &Wall, back: &Wall) -> bool {
+    carry(&back.transform, &back.a) == wall.b && carry(&back.transform, &back.b) == wall.a
+}
+
+/// Checks that every portal is mirrored by a wall in the target sector with
+/// reversed endpoints that portals straight back. For a transforming portal
+/// the endpoints are compared after carrying them through, and the wall
+/// leading back has to carry them back again.
+pub fn check_portals(sectors: &Sectors, walls: &Walls) -> Vec<PortalProblem> {
+    let mut problems = vec![];
+
+    for sector in 1..sectors.len() {
+        for i in wall_range(sectors, walls, sector) {
+            let wall = &walls.arr[i];
+            let target = wall.portal;
+
+            // out of range portals are validate_level's business
+            if target as i32 == SECTOR_NON || target >= sectors.len() {
+                continue;
+            }
+
+            let mut back_walls = wall_range(sectors, walls, target);
+            let (a, b) = (
+                carry(&wall.transform, &wall.a),
+                carry(&wall.transform, &wall.b),
+            );
+
+            if let Some(back) = back_walls
+                .clone()
+                .find(|&j| walls.arr[j].a == b && walls.arr[j].b == a)
+            {
+                let back_portal = walls.arr[back].portal;
+                if back_portal == sector && carries_back(wall, &walls.arr[back]) {
+                    continue;
+                }
+
+                problems.push(if back_portal as i32 == SECTOR_NON {
+                    PortalProblem::NoBackLink {
+                        sector,
+                        wall: i,
+                        target,
+                        back,
+                    }
+                } else if back_portal == sector {
+                    PortalProblem::MismatchedTransform {
+                        sector,
+                        wall: i,
+                        target,
+                        back,
+                    }
+                } else {
+                    PortalProblem::WrongBackLink {
+                        sector,
+                        wall: i,
+                        target,
+                        back,
+                        back_portal,
+                    }
+                });
+            } else if let Some(back) = back_walls.find(|&j| walls.arr[j].portal == sector) {
+                problems.push(PortalProblem::MismatchedEndpoints {
+                    sector,
+                    wall: i,
+                    target,
+                    back,
+                });
+            } else {
+                problems.push(PortalProblem::OneWay {
+                    sector,
+                    wall: i,
+                    target,
+                });
+            }
+        }
+    }
+
+    problems
+}
+
+/// Whether `link_portals` may touch `wall`. Transforming portals can't be
+/// found from geometry, and mirrors and textured solid walls are walls on
+/// purpose.
+fn linkable(wall: &Wall) -> bool {
+    wall.transform.is_none()
+        && !wall.mirror
+        && (wall.portal as i32 != SECTOR_NON || wall.texture == TEXTURE_NON)
+}
+
+/// Links walls that coincide with a reversed wall of another sector, so
+/// levels only need their geometry drawn. Returns how many walls changed.
+/// Walls that aren't `linkable` are left as they are and nothing is linked
+/// to them.
+pub fn link_portals(sectors: &Sectors, walls: &mut Walls, mode: LinkMode) -> usize {
+    let mut owners: HashMap<(V2i, V2i), usize> = HashMap::new();
+
+    for sector in 1..sectors.len() {
+        for i in wall_range(sectors, walls, sector) {
+            let wall = &walls.arr[i];
+            if linkable(wall) {
+                owners.insert((wall.a.clone(), wall.b.clone()), sector);
+            }
+        }
+    }
+
+    let mut changed = 0;
+
+    for sector in 1..sectors.len() {
+        for i in wall_range(sectors, walls, sector) {
+            let wall = &mut walls.arr[i];
+            if !linkable(wall) || (mode == LinkMode::Fill && wall.portal as i32 != SECTOR_NON) {
+                continue;
+            }
+
+            let twin = owners
+                .get(&(wall.b.clone(), wall.a.clone()))
+                .copied()
+                .filter(|&owner| owner != sector);
+
+            let portal = match twin {
+                Some(owner) => owner,
+                None if mode == LinkMode::Rebuild => SECTOR_NON as usize,
+                None => continue,
+            };
+
+            if wall.portal != portal {
+                wall.portal = portal;
+                changed += 1;
+            }
+        }
+    }
+
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::res::level::validate::validate_level;
+    use crate::res::level::{read_level, read_level_with};
+    use crate::res::testing::TempFile;
+    use crate::res::util::kinds::Sector;
+
+    /// Two 4x4 rooms side by side, sharing the wall at x = 4, unlinked.
+    fn rooms() -> (Sectors, Walls) {
+        let mut sectors = Sectors::new();
+        let mut walls = Walls::new();
+        for (id, x) in [(1, 0), (2, 4)] {
+            let corners = [(x, 0), (x, 4), (x + 4, 4), (x + 4, 0)];
+            sectors.arr.push(Sector {
+                id,
+                firstwall: walls.len(),
+                nwalls: 4,
+                zceil: 4.0,
+                ..Default::default()
+            });
+            for i in 0..4 {
+                let (a, b) = (corners[i], corners[(i + 1) % 4]);
+                walls.arr.push(Wall {
+                    a: V2i::new(a.0, a.1),
+                    b: V2i::new(b.0, b.1),
+                    ..Default::default()
+                });
+            }
+        }
+        (sectors, walls)
+    }
+
+    /// `rooms` with the shared wall a portal both ways: wall 2 of room 1
+    /// and wall 4 of room 2.
+    fn linked_rooms() -> (Sectors, Walls) {
+        let (sectors, mut walls) = rooms();
+        walls.arr[2].portal = 2;
+        walls.arr[4].portal = 1;
+        (sectors, walls)
+    }
+
+    #[test]
+    fn linked_rooms_are_fine() {
+        let (sectors, walls) = linked_rooms();
+        assert_eq!(check_portals(&sectors, &walls), vec![]);
+    }
+
+    #[test]
+    fn one_way() {
+        let (sectors, mut walls) = rooms();
+        walls.arr[0].portal = 2;
+        assert_eq!(
+            check_portals(&sectors, &walls),
+            vec![PortalProblem::OneWay {
+                sector: 1,
+                wall: 0,
+                target: 2,
+            }]
+        );
+    }
+
+    #[test]
+    fn no_back_link() {
+        let (sectors, mut walls) = rooms();
+        walls.arr[2].portal = 2;
+        assert_eq!(
+            check_portals(&sectors, &walls),
+            vec![PortalProblem::NoBackLink {
+                sector: 1,
+                wall: 2,
+                target: 2,
+                back: 4,
+            }]
+        );
+    }
+
+    #[test]
+    fn mismatched_endpoints() {
+        // room 2 only leads back through the shared wall
+        let (sectors, mut walls) = linked_rooms();
+        walls.arr[0].portal = 2;
+        assert_eq!(
+            check_portals(&sectors, &walls),
+            vec![PortalProblem::MismatchedEndpoints {
+                sector: 1,
+                wall: 0,
+                target: 2,
+                back: 4,
+            }]
+        );
+    }
+
+    #[test]
+    fn fill_links_twins() {
+        let (sectors, mut walls) = rooms();
+        assert_eq!(link_portals(&sectors, &mut walls, LinkMode::Fill), 2);
+        assert_eq!(walls, linked_rooms().1);
+    }
+
+    #[test]
+    fn fill_keeps_existing_portals() {
+        let (sectors, mut walls) = linked_rooms();
+        walls.arr[0].portal = 2;
+        let before = walls.clone();
+        assert_eq!(link_portals(&sectors, &mut walls, LinkMode::Fill), 0);
+        assert_eq!(walls, before);
+    }
+
+    #[test]
+    fn rebuild_clears_portals_without_twins() {
+        let (sectors, mut walls) = linked_rooms();
+        walls.arr[0].portal = 2;
+        assert_eq!(link_portals(&sectors, &mut walls, LinkMode::Rebuild), 1);
+        assert_eq!(walls, linked_rooms().1);
+    }
+
+    #[test]
+    fn transformed_walls_are_left_alone() {
+        let (sectors, mut walls) = rooms();
+        walls.arr[2].transform = Some(PortalTransform {
+            offset: crate::res::util::kinds::V2::new(0.0, 0.0),
+            angle: 0.0,
+        });
+        assert_eq!(link_portals(&sectors, &mut walls, LinkMode::Rebuild), 0);
+        assert_eq!(walls.arr[2].portal, 0);
+        assert_eq!(walls.arr[4].portal, 0);
+    }
+
+    #[test]
+    fn mirrors_are_left_alone() {
+        // a mirror on the wall the two rooms share, seen from room 1
+        for mode in [LinkMode::Fill, LinkMode::Rebuild] {
+            let (sectors, mut walls) = rooms();
+            walls.arr[2].mirror = true;
+            assert_eq!(link_portals(&sectors, &mut walls, mode), 0);
+            assert_eq!(walls.arr[2].portal, 0);
+            assert_eq!(walls.arr[4].portal, 0);
+            assert_eq!(validate_level(&sectors, &walls), vec![]);
+        }
+    }
+
+    #[test]
+    fn textured_solid_walls_are_left_alone() {
+        for mode in [LinkMode::Fill, LinkMode::Rebuild] {
+            let (sectors, mut walls) = rooms();
+            walls.arr[2].texture = 1;
+            assert_eq!(link_portals(&sectors, &mut walls, mode), 0);
+            assert_eq!(walls.arr[2].portal, 0);
+            assert_eq!(walls.arr[4].portal, 0);
+        }
+    }
+
+    #[test]
+    fn only_read_level_with_links() {
+        // the door from room 1 to room 2 is only drawn from room 1
+        let file = TempFile::new("one-sided.txt");
+        std::fs::write(
+            file.path(),
+            "[SECTOR]\n1 0 4 0 4\n2 4 4 0 4\n\n\
+             [WALL]\n0 0 0 4 0\n0 4 4 4 0\n4 4 4 0 2\n4 0 0 0 0\n\
+             4 0 4 4 0\n4 4 8 4 0\n8 4 8 0 0\n8 0 4 0 0\n",
+        )
+        .unwrap();
+
+        let level = read_level(file.path()).unwrap();
+        assert_eq!(level.walls.arr[4].portal, 0);
+
+        let level = read_level_with(file.path(), LinkMode::Fill).unwrap();
+        assert_eq!(level.walls.arr[4].portal, 1);
+    }
+}
@@ -0,0 +1,372 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use super::Level;
+use crate::res::util::constants::{LIGHT_FULL, MIRROR_TEXTURE, SKY_TEXTURE, TEXTURE_NON};
+use crate::res::util::math::{deg_2_rad, rad_2_deg};
+
+/// Writes a level's title, sectors, walls, slopes, portal transforms, player
+/// start and things back out in the text format read by `load_sectors`.
+/// Texture `i` is saved as `level.textures[i - 1]`, which it is both as read
+/// and once bound to a texture registry. Names the format can't hold fail
+/// with `InvalidInput`, before anything is written to `path`.
+pub fn save_level(path: &str, level: &Level) -> io::Result<()> {
+    let mut text = vec![];
+    write_level(&mut text, level)?;
+
+    let mut out = BufWriter::new(File::create(path)?);
+    out.write_all(&text)?;
+    out.flush()
+}
+
+fn unsaveable(what: &str, name: &str, why: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("{} \"{}\" can't be saved, {}", what, name, why),
+    )
+}
+
+/// `name` if it reads back as the same single field of a line: not empty,
+/// without whitespace or `#`, and not starting a section header.
+fn field<'a>(what: &str, name: &'a str) -> io::Result<&'a str> {
+    if name.is_empty() || name.contains(char::is_whitespace) || name.contains('#') {
+        Err(unsaveable(
+            what,
+            name,
+            "names can't be empty or hold spaces or `#`",
+        ))
+    } else if name.starts_with('[') {
+        Err(unsaveable(what, name, "names can't start with `[`"))
+    } else {
+        Ok(name)
+    }
+}
+
+/// `title` if it reads back the same as the line of a `[TITLE]` section.
+fn title_line(title: &str) -> io::Result<&str> {
+    if title.is_empty() || title.trim() != title || title.contains(['\n', '\r']) {
+        Err(unsaveable(
+            "title",
+            title,
+            "it must be one line without spaces at either end",
+        ))
+    } else if title.starts_with(['#', '[']) {
+        Err(unsaveable("title", title, "it can't start with `#` or `[`"))
+    } else {
+        Ok(title)
+    }
+}
+
+/// Degrees that read back as exactly `angle`, rather than just close to it, so
+/// saving and reloading a level leaves its angles alone.
+fn degrees(angle: f32) -> f32 {
+    let (mut up, mut down) = (rad_2_deg(angle), rad_2_deg(angle));
+    // the two conversions round at most a few ulps apart
+    for _ in 0..8 {
+        if deg_2_rad(up) == angle {
+            return up;
+        }
+        if deg_2_rad(down) == angle {
+            return down;
+        }
+        up = up.next_up();
+        down = down.next_down();
+    }
+    rad_2_deg(angle)
+}
+
+pub fn write_level<W: Write>(out: &mut W, level: &Level) -> io::Result<()> {
+    let (sectors, walls) = (&level.sectors, &level.walls);
+    // "-" is no texture, so no texture can be called that
+    let texture = |r: usize| match r {
+        TEXTURE_NON => Ok("-"),
+        r => match level.textures.get(r - 1).map(|name| name.as_str()) {
+            Some("-") => Err(unsaveable("texture", "-", "it would read back as none")),
+            Some(name) => field("texture", name),
+            None => Ok("-"),
+        },
+    };
+
+    if let Some(title) = &level.title {
+        writeln!(out, "[TITLE]")?;
+        writeln!(out, "{}", title_line(title)?)?;
+        writeln!(out)?;
+    }
+
+    writeln!(out, "[SECTOR]")?;
+
+    // sector 0 does not exist
+    for sector in sectors.arr.iter().skip(1) {
+        // {:?} keeps the ".0" and round-trips every f32 exactly
+        write!(
+            out,
+            "{} {} {} {:?} {:?}",
+            sector.id, sector.firstwall, sector.nwalls, sector.zfloor, sector.zceil
+        )?;
+
+        let ceil = match sector.sky {
+            true => SKY_TEXTURE,
+            false => texture(sector.ceil)?,
+        };
+        if sector.light != LIGHT_FULL {
+            writeln!(
+                out,
+                " {} {} {:?}",
+                texture(sector.floor)?,
+                ceil,
+                sector.light
+            )?;
+        } else if sector.ceil != 0 || sector.sky {
+            writeln!(out, " {} {}", texture(sector.floor)?, ceil)?;
+        } else if sector.floor != 0 {
+            writeln!(out, " {}", texture(sector.floor)?)?;
+        } else {
+            writeln!(out)?;
+        }
+    }
+
+    writeln!(out)?;
+    writeln!(out, "[WALL]")?;
+
+    // walls are written in index order so firstwall stays valid, with a
+    // comment header wherever a sector's walls start
+    for (i, wall) in walls.arr.iter().enumerate() {
+        for (j, sector) in sectors.arr.iter().enumerate().skip(1) {
+            if sector.firstwall == i && sector.nwalls > 0 {
+                if i != 0 {
+                    writeln!(out)?;
+                }
+                writeln!(
+                    out,
+                    "# SECTOR {}: {}..{}",
+                    j,
+                    sector.firstwall,
+                    sector.firstwall + sector.nwalls - 1
+                )?;
+            }
+        }
+
+        write!(
+            out,
+            "{} {} {} {} {}",
+            wall.a.x, wall.a.y, wall.b.x, wall.b.y, wall.portal
+        )?;
+
+        // texture fields only as far as they are needed
+        let wall_texture = match wall.mirror {
+            true => MIRROR_TEXTURE,
+            false => texture(wall.texture)?,
+        };
+        if wall.upper != 0 || wall.lower != 0 {
+            writeln!(
+                out,
+                " {} {} {}",
+                wall_texture,
+                texture(wall.upper)?,
+                texture(wall.lower)?
+            )?;
+        } else if wall.texture != 0 || wall.mirror {
+            writeln!(out, " {}", wall_texture)?;
+        } else {
+            writeln!(out)?;
+        }
+    }
+
+    let slopes: Vec<_> = sectors
+        .arr
+        .iter()
+        .enumerate()
+        .skip(1)
+        .flat_map(|(i, sector)| {
+            [("floor", &sector.floor_slope), ("ceil", &sector.ceil_slope)]
+                .into_iter()
+                .filter_map(move |(which, slope)| Some((i, which, slope.as_ref()?)))
+        })
+        .collect();
+    if !slopes.is_empty() {
+        writeln!(out)?;
+        writeln!(out, "[SLOPE]")?;
+
+        for (i, which, slope) in slopes {
+            writeln!(out, "{} {} {} {:?}", i, which, slope.wall, slope.delta)?;
+        }
+    }
+
+    if walls.arr.iter().any(|wall| wall.transform.is_some()) {
+        writeln!(out)?;
+        writeln!(out, "[TRANSFORM]")?;
+
+        for (i, wall) in walls.arr.iter().enumerate() {
+            if let Some(transform) = &wall.transform {
+                writeln!(
+                    out,
+                    "{} {:?} {:?} {:?}",
+                    i,
+                    transform.offset.x,
+                    transform.offset.y,
+                    degrees(transform.angle)
+                )?;
+            }
+        }
+    }
+
+    if let Some(player) = &level.player {
+        writeln!(out)?;
+        writeln!(out, "[PLAYER]")?;
+
+        write!(
+            out,
+            "{:?} {:?} {:?}",
+            player.pos.x,
+            player.pos.y,
+            degrees(player.angle)
+        )?;
+        match player.sector {
+            Some(sector) => writeln!(out, " {}", sector)?,
+            None => writeln!(out)?,
+        }
+    }
+
+    if !level.things.is_empty() {
+        writeln!(out)?;
+        writeln!(out, "[THING]")?;
+
+        for thing in &level.things {
+            writeln!(
+                out,
+                "{} {:?} {:?} {} {:?}",
+                field("thing", &thing.clone().get_name())?,
+                thing.pos.x,
+                thing.pos.y,
+                texture(thing.sprite)?,
+                thing.height
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internaltypes::thing::Thing;
+    use crate::res::level::read_level;
+    use crate::res::level::text::read_text;
+    use crate::res::level::PlayerStart;
+    use crate::res::testing::{root, TempFile};
+    use crate::res::texture::Textures;
+    use crate::res::util::kinds::V2;
+
+    /// The texture names a level's sectors, walls and things use, in order.
+    fn texture_names(level: &Level) -> Vec<Option<&str>> {
+        let sectors = level.sectors.arr.iter().flat_map(|s| [s.floor, s.ceil]);
+        let walls = level
+            .walls
+            .arr
+            .iter()
+            .flat_map(|w| [w.texture, w.upper, w.lower]);
+        let things = level.things.iter().map(|t| t.sprite);
+        sectors
+            .chain(walls)
+            .chain(things)
+            .map(|r| match r {
+                TEXTURE_NON => None,
+                r => level.textures.get(r - 1).map(|name| name.as_str()),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn bound_levels_keep_their_texture_names() {
+        let source = root().join("tests/levels/textured.txt");
+        let level = read_text(source.to_str().unwrap()).unwrap();
+
+        // the registry numbers the textures differently from the level
+        let mut bound = level.clone();
+        assert_eq!(Textures::builtin().bind(&mut bound), Vec::<String>::new());
+        assert_ne!(bound.walls, level.walls);
+
+        let file = TempFile::new("bound.txt");
+        save_level(file.path(), &bound).unwrap();
+        let saved = read_text(file.path()).unwrap();
+
+        assert_eq!(texture_names(&bound), texture_names(&level));
+        assert_eq!(texture_names(&saved), texture_names(&level));
+    }
+
+    #[test]
+    fn levels_round_trip() {
+        let mut paths = vec![root().join("level.txt")];
+        for entry in std::fs::read_dir(root().join("tests/levels")).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|ext| ext == "txt") {
+                paths.push(path);
+            }
+        }
+        assert!(paths.len() > 1, "no test levels found");
+
+        for path in paths {
+            let path = path.to_str().unwrap();
+            let level = read_level(path).unwrap_or_else(|e| panic!("{}", e));
+
+            let file = TempFile::new("round-trip.txt");
+            save_level(file.path(), &level).unwrap();
+            let saved = read_level(file.path()).unwrap_or_else(|e| panic!("{}", e));
+            assert_eq!(saved, level, "{} changed when saved", path);
+        }
+    }
+
+    #[test]
+    fn player_and_title_round_trip() {
+        let source = root().join("level.txt");
+        let mut level = read_text(source.to_str().unwrap()).unwrap();
+        level.title = Some("The  Cellar".to_string());
+        level.player = Some(PlayerStart {
+            pos: V2::new(2.5, 3.25),
+            angle: 1.5,
+            sector: Some(2),
+        });
+
+        let file = TempFile::new("player-title.txt");
+        save_level(file.path(), &level).unwrap();
+        let saved = read_text(file.path()).unwrap();
+
+        assert_eq!(saved.title.as_deref(), Some("The  Cellar"));
+        assert_eq!(saved.player, level.player);
+    }
+
+    #[test]
+    fn names_that_would_not_read_back_are_rejected() {
+        let source = root().join("tests/levels/textured.txt");
+        let level = read_text(source.to_str().unwrap()).unwrap();
+        assert!(!level.things.is_empty());
+
+        let mut spaced_thing = level.clone();
+        spaced_thing.things[0] = Thing::new("big lamp".to_string());
+        let mut spaced_texture = level.clone();
+        spaced_texture.textures[0] = "red brick".to_string();
+        let mut hashed_texture = level.clone();
+        hashed_texture.textures[0] = "brick#2".to_string();
+        let mut none_texture = level.clone();
+        none_texture.textures[0] = "-".to_string();
+        let mut header_title = level.clone();
+        header_title.title = Some("[SECTOR]".to_string());
+        let mut spaced_title = level.clone();
+        spaced_title.title = Some("The Cellar ".to_string());
+
+        for bad in [
+            spaced_thing,
+            spaced_texture,
+            hashed_texture,
+            none_texture,
+            header_title,
+            spaced_title,
+        ] {
+            let file = TempFile::new("bad-name.txt");
+            let error = save_level(file.path(), &bad).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput, "{}", error);
+            assert!(!std::path::Path::new(file.path()).exists());
+        }
+    }
+}
@@ -0,0 +1,440 @@
+use std::collections::HashMap;
+
+use ron::extensions::Extensions;
+use ron::Options;
+use serde::Deserialize;
+
+use super::error::{LevelError, Location};
+use super::{Level, PlayerStart};
+use crate::internaltypes::thing::Thing;
+use crate::res::util::constants::{LIGHT_FULL, TEXTURE_NON};
+use crate::res::util::kinds::{PortalTransform, Sector, Slope, V2i, Wall, V2};
+use crate::res::util::math::deg_2_rad;
+
+/// File extension of structured levels.
+pub const EXTENSION: &str = "ron";
+
+/// A structured level. Walls are listed under the sector that owns them, so
+/// `firstwall`/`nwalls` are worked out on load, and every field that isn't
+/// needed to draw the level is optional:
+///
+/// ```ron
+/// (
+///     title: "Cellar",
+///     player: (pos: (3.0, 2.0), angle: 90.0, sector: "hall"),
+///     sectors: [
+///         (
+///             name: "hall",
+///             zfloor: 0.0,
+///             zceil: 5.0,
+///             floor: "checker",
+///             light: 0.6,
+///             ceil_slope: (wall: 2, delta: -0.5),
+///             walls: [
+///                 (a: (4, 1), b: (2, 1)),
+///                 (a: (4, 4), b: (5, 3), portal: "stairs", upper: "planks"),
+///                 (a: (2, 4), b: (4, 4), texture: "bricks"),
+///                 (a: (2, 1), b: (2, 4), portal: "attic", transform: (offset: (30.0, 0.0))),
+///                 ...
+///             ],
+///         ),
+///     ],
+///     things: [
+///         (name: "lamp", pos: (2.5, 2.0), sprite: "orb", height: 0.5),
+///     ],
+/// )
+/// ```
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LevelFile {
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    player: Option<PlayerFile>,
+    sectors: Vec<SectorFile>,
+    #[serde(default)]
+    things: Vec<ThingFile>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PlayerFile {
+    pos: (f32, f32),
+    /// degrees
+    #[serde(default)]
+    angle: f32,
+    #[serde(default)]
+    sector: Option<SectorRef>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SectorFile {
+    name: String,
+    zfloor: f32,
+    zceil: f32,
+    /// texture names, a ceiling of `sky` opening the sector to the sky
+    #[serde(default)]
+    floor: Option<String>,
+    #[serde(default)]
+    ceil: Option<String>,
+    /// `LIGHT_FULL` if not given
+    #[serde(default)]
+    light: Option<f32>,
+    /// flat if not given
+    #[serde(default)]
+    floor_slope: Option<SlopeFile>,
+    #[serde(default)]
+    ceil_slope: Option<SlopeFile>,
+    walls: Vec<WallFile>,
+}
+
+/// A `Slope`, about the sector's `wall`th wall counting from 0.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SlopeFile {
+    wall: usize,
+    delta: f32,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WallFile {
+    a: (i32, i32),
+    b: (i32, i32),
+    #[serde(default)]
+    portal: Option<SectorRef>,
+    /// texture names, a texture of `mirror` making the wall a mirror
+    #[serde(default)]
+    texture: Option<String>,
+    #[serde(default)]
+    upper: Option<String>,
+    #[serde(default)]
+    lower: Option<String>,
+    /// for a portal into a sector elsewhere in the map
+    #[serde(default)]
+    transform: Option<TransformFile>,
+}
+
+/// A `PortalTransform`, turning by `angle` degrees and then moving by
+/// `offset`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TransformFile {
+    #[serde(default)]
+    offset: (f32, f32),
+    #[serde(default)]
+    angle: f32,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ThingFile {
+    name: String,
+    pos: (f32, f32),
+    /// texture name
+    #[serde(default)]
+    sprite: Option<String>,
+    #[serde(default)]
+    height: Option<f32>,
+}
+
+/// A sector given by name, or by index as in the text format.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SectorRef {
+    Index(usize),
+    Name(String),
+}
+
+fn bad_reference(path: &str, message: String) -> LevelError {
+    LevelError::BadReference {
+        path: path.to_string(),
+        message,
+    }
+}
+
+/// The texture reference for an optional texture name.
+fn texture(level: &mut Level, name: &Option<String>) -> usize {
+    match name {
+        Some(name) => level.texture_ref(name),
+        None => TEXTURE_NON,
+    }
+}
+
+fn slope(slope: &SlopeFile) -> Slope {
+    Slope {
+        wall: slope.wall,
+        delta: slope.delta,
+    }
+}
+
+fn transform(transform: &TransformFile) -> PortalTransform {
+    PortalTransform {
+        offset: V2::new(transform.offset.0, transform.offset.1),
+        angle: deg_2_rad(transform.angle),
+    }
+}
+
+/// Reads the structured RON level format.
+pub fn read_structured(path: &str) -> Result<Level, LevelError> {
+    let source = std::fs::read_to_string(path).map_err(|error| LevelError::open(path, error))?;
+
+    let file: LevelFile = Options::default()
+        .with_default_extension(Extensions::IMPLICIT_SOME)
+        .from_str(&source)
+        .map_err(|error| {
+            let line = error.position.line;
+            LevelError::Syntax {
+                at: Location::new(
+                    path,
+                    line,
+                    source.lines().nth(line.saturating_sub(1)).unwrap_or(""),
+                    usize::max(error.position.col, 1),
+                    "",
+                ),
+                message: error.code.to_string(),
+            }
+        })?;
+
+    // sector 0 does not exist, named sectors start at 1
+    let mut names = HashMap::new();
+    for (i, sector) in file.sectors.iter().enumerate() {
+        if names.insert(sector.name.as_str(), i + 1).is_some() {
+            return Err(bad_reference(
+                path,
+                format!("sector name \"{}\" is used twice", sector.name),
+            ));
+        }
+    }
+
+    let resolve = |r: &SectorRef, user: &str| -> Result<usize, LevelError> {
+        match r {
+            SectorRef::Index(i) if (1..=file.sectors.len()).contains(i) => Ok(*i),
+            SectorRef::Index(i) => Err(bad_reference(
+                path,
+                format!("{} refers to sector {} which does not exist", user, i),
+            )),
+            SectorRef::Name(name) => names.get(name.as_str()).copied().ok_or_else(|| {
+                bad_reference(
+                    path,
+                    format!("{} refers to unknown sector \"{}\"", user, name),
+                )
+            }),
+        }
+    };
+
+    let mut level = Level {
+        title: file.title.clone(),
+        ..Default::default()
+    };
+
+    for (i, sector) in file.sectors.iter().enumerate() {
+        let firstwall = level.walls.len();
+
+        for (j, wall) in sector.walls.iter().enumerate() {
+            let portal = match &wall.portal {
+                Some(r) => resolve(r, &format!("wall {} of sector \"{}\"", j, sector.name))?,
+                None => 0,
+            };
+
+            let (wall_texture, mirror) = match &wall.texture {
+                Some(name) => level.wall_ref(name),
+                None => (TEXTURE_NON, false),
+            };
+            let (upper, lower) = (
+                texture(&mut level, &wall.upper),
+                texture(&mut level, &wall.lower),
+            );
+
+            level.walls.arr.push(Wall {
+                a: V2i::new(wall.a.0, wall.a.1),
+                b: V2i::new(wall.b.0, wall.b.1),
+                portal,
+                texture: wall_texture,
+                upper,
+                lower,
+                transform: wall.transform.as_ref().map(transform),
+                mirror,
+            });
+        }
+
+        let floor = texture(&mut level, &sector.floor);
+        let (ceil, sky) = match &sector.ceil {
+            Some(name) => level.ceiling_ref(name),
+            None => (TEXTURE_NON, false),
+        };
+        level.sectors.arr.push(Sector {
+            id: (i + 1) as i32,
+            firstwall,
+            nwalls: sector.walls.len(),
+            zfloor: sector.zfloor,
+            zceil: sector.zceil,
+            floor,
+            ceil,
+            light: sector.light.unwrap_or(LIGHT_FULL),
+            sky,
+            floor_slope: sector.floor_slope.as_ref().map(slope),
+            ceil_slope: sector.ceil_slope.as_ref().map(slope),
+        });
+    }
+
+    level.player = match &file.player {
+        Some(player) => Some(PlayerStart {
+            pos: V2::new(player.pos.0, player.pos.1),
+            angle: deg_2_rad(player.angle),
+            sector: match &player.sector {
+                Some(r) => Some(resolve(r, "player start")?),
+                None => None,
+            },
+        }),
+        None => None,
+    };
+
+    for thing in &file.things {
+        let mut placed = Thing::new(thing.name.clone());
+        placed.pos = V2::new(thing.pos.0, thing.pos.1);
+        placed.sprite = texture(&mut level, &thing.sprite);
+        if let Some(height) = thing.height {
+            placed.height = height;
+        }
+        level.things.push(placed);
+    }
+
+    Ok(level)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::res::testing::TempFile;
+
+    /// Two square rooms side by side, `west` and `east`, the player
+    /// starting in `east` and the door between them given as `door`.
+    fn rooms(door: &str) -> String {
+        format!(
+            r#"(
+    title: "Rooms",
+    player: (pos: (6.0, 2.0), angle: 90.0, sector: "east"),
+    sectors: [
+        (
+            name: "west",
+            zfloor: 0.0,
+            zceil: 4.0,
+            walls: [
+                (a: (0, 0), b: (0, 4), texture: "bricks"),
+                (a: (0, 4), b: (4, 4), texture: "bricks"),
+                (a: (4, 4), b: (4, 0), portal: {}),
+                (a: (4, 0), b: (0, 0), texture: "bricks"),
+            ],
+        ),
+        (
+            name: "east",
+            zfloor: 1.0,
+            zceil: 4.0,
+            ceil: "sky",
+            walls: [
+                (a: (4, 0), b: (4, 4), portal: "west"),
+                (a: (4, 4), b: (8, 4), texture: "mirror"),
+                (a: (8, 4), b: (8, 0), texture: "planks"),
+                (a: (8, 0), b: (4, 0), texture: "planks"),
+            ],
+        ),
+    ],
+    things: [(name: "lamp", pos: (2.0, 2.0), sprite: "orb", height: 0.5)],
+)
+"#,
+            door
+        )
+    }
+
+    fn read(name: &str, source: &str) -> Result<Level, LevelError> {
+        let file = TempFile::new(name);
+        std::fs::write(file.path(), source).unwrap();
+        read_structured(file.path())
+    }
+
+    fn bad_reference_message(result: Result<Level, LevelError>) -> String {
+        match result {
+            Err(LevelError::BadReference { message, .. }) => message,
+            other => panic!("expected BadReference, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn names_resolve_to_sectors() {
+        let level = read("names.ron", &rooms("\"east\"")).unwrap();
+
+        assert_eq!(level.title.as_deref(), Some("Rooms"));
+        assert_eq!(level.sectors.len(), 3);
+        assert_eq!(level.sectors.arr[1].id, 1);
+        assert_eq!(level.sectors.arr[2].id, 2);
+        assert_eq!(
+            (level.sectors.arr[2].firstwall, level.sectors.arr[2].nwalls),
+            (4, 4)
+        );
+        assert_eq!(level.walls.arr[2].portal, 2);
+        assert_eq!(level.walls.arr[4].portal, 1);
+        assert_eq!(level.player.as_ref().unwrap().sector, Some(2));
+
+        assert!(level.sectors.arr[2].sky);
+        assert!(level.walls.arr[5].mirror);
+        assert_eq!(level.textures, ["bricks", "planks", "orb"]);
+        assert_eq!(level.things[0].sprite, 3);
+        assert_eq!(level.things[0].height, 0.5);
+    }
+
+    #[test]
+    fn indices_resolve_like_names() {
+        let by_name = read("by-name.ron", &rooms("\"east\"")).unwrap();
+        let by_index = read("by-index.ron", &rooms("2")).unwrap();
+        assert_eq!(by_index, by_name);
+
+        let message = bad_reference_message(read("index-0.ron", &rooms("0")));
+        assert_eq!(
+            message,
+            "wall 2 of sector \"west\" refers to sector 0 which does not exist"
+        );
+        let message = bad_reference_message(read("index-3.ron", &rooms("3")));
+        assert_eq!(
+            message,
+            "wall 2 of sector \"west\" refers to sector 3 which does not exist"
+        );
+    }
+
+    #[test]
+    fn duplicate_names_are_rejected() {
+        let source = rooms("\"east\"").replace("name: \"east\"", "name: \"west\"");
+        let message = bad_reference_message(read("duplicate.ron", &source));
+        assert_eq!(message, "sector name \"west\" is used twice");
+    }
+
+    #[test]
+    fn unknown_names_are_rejected() {
+        let message = bad_reference_message(read("unknown.ron", &rooms("\"attic\"")));
+        assert_eq!(
+            message,
+            "wall 2 of sector \"west\" refers to unknown sector \"attic\""
+        );
+
+        let source = rooms("\"east\"").replace("sector: \"east\"", "sector: \"cellar\"");
+        let message = bad_reference_message(read("unknown-player.ron", &source));
+        assert_eq!(message, "player start refers to unknown sector \"cellar\"");
+    }
+
+    #[test]
+    fn syntax_errors_point_at_line_and_column() {
+        // without the comma after zfloor on line 7, zceil on line 8 is
+        // where parsing fails
+        let source = rooms("\"east\"").replacen("zfloor: 0.0,", "zfloor: 0.0", 1);
+        let (at, message) = match read("syntax.ron", &source) {
+            Err(LevelError::Syntax { at, message }) => (at, message),
+            other => panic!("expected Syntax, got {:?}", other.map(|_| ())),
+        };
+
+        assert_eq!(at.line, 8);
+        assert_eq!(at.source, "            zceil: 4.0,");
+        assert_eq!(at.column, 13);
+        assert!(!message.is_empty());
+    }
+}
@@ -0,0 +1,331 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::str::FromStr;
+
+use super::error::{LevelError, Location};
+use super::{Level, PlayerStart};
+use crate::internaltypes::thing::Thing;
+use crate::res::util::constants::{LIGHT_FULL, TEXTURE_NON};
+use crate::res::util::kinds::{PortalTransform, Sector, Slope, V2i, Wall, V2};
+use crate::res::util::math::deg_2_rad;
+
+pub enum ScanState {
+    ScanSector,
+    ScanWall,
+    ScanPlayer,
+    ScanTitle,
+    ScanThing,
+    ScanSlope,
+    ScanTransform,
+    ScanNone,
+}
+
+/// One line of a level file, for building error locations.
+struct LineCtx<'a> {
+    path: &'a str,
+    line: usize,
+    source: &'a str,
+}
+
+impl<'a> LineCtx<'a> {
+    fn at(&self, column: usize, token: &str) -> Location {
+        Location::new(self.path, self.line, self.source, column, token)
+    }
+
+    /// Whitespace separated fields along with their 1-based columns.
+    fn fields(&self) -> Vec<(usize, &'a str)> {
+        let source = self.source;
+        source
+            .split_whitespace()
+            .map(|tok| {
+                let offset = tok.as_ptr() as usize - source.as_ptr() as usize;
+                (source[..offset].chars().count() + 1, tok)
+            })
+            .collect()
+    }
+
+    fn check_count(
+        &self,
+        fields: &[(usize, &str)],
+        section: &'static str,
+        expected: usize,
+    ) -> Result<(), LevelError> {
+        if fields.len() == expected {
+            return Ok(());
+        }
+
+        // point at the first extra field, or just past the end of the line
+        let at = fields
+            .get(expected)
+            .map(|&(column, token)| self.at(column, token))
+            .unwrap_or_else(|| self.at(self.source.trim_end().chars().count() + 1, ""));
+
+        Err(LevelError::FieldCount {
+            at,
+            section,
+            expected,
+            found: fields.len(),
+        })
+    }
+
+    fn parse<T: FromStr>(
+        &self,
+        (column, token): (usize, &str),
+        expected: &'static str,
+    ) -> Result<T, LevelError> {
+        token.parse::<T>().map_err(|_| LevelError::BadNumber {
+            at: self.at(column, token),
+            expected,
+        })
+    }
+}
+
+/// Reads the `[SECTOR]`/`[WALL]`/`[SLOPE]`/`[TRANSFORM]`/`[PLAYER]`/`[THING]`/
+/// `[TITLE]` text format.
+pub fn read_text(path: &str) -> Result<Level, LevelError> {
+    let mut level = Level::default();
+
+    let f = File::open(path).map_err(|error| LevelError::open(path, error))?;
+    let reader = BufReader::new(f);
+
+    let mut ss = ScanState::ScanNone;
+
+    // slopes and transforms can be given before the sectors and walls they
+    // belong to
+    let mut slopes = vec![];
+    let mut transforms = vec![];
+
+    for (i, line) in reader.lines().enumerate() {
+        let line = line.map_err(|error| LevelError::read(path, i + 1, error))?;
+        let ctx = LineCtx {
+            path,
+            line: i + 1,
+            source: &line,
+        };
+
+        let p = line.trim_start();
+        let column = line[..line.len() - p.len()].chars().count() + 1;
+        if p.is_empty() || p.starts_with('#') {
+            continue;
+        } else if let Some(header) = p.strip_prefix('[') {
+            let Some((section, _)) = header.split_once(']') else {
+                return Err(LevelError::UnknownSection {
+                    at: ctx.at(column, p.trim_end()),
+                    message: "section header is missing its closing `]`".to_string(),
+                });
+            };
+
+            match section {
+                "SECTOR" => ss = ScanState::ScanSector,
+                "WALL" => ss = ScanState::ScanWall,
+                "PLAYER" => ss = ScanState::ScanPlayer,
+                "TITLE" => ss = ScanState::ScanTitle,
+                "THING" => ss = ScanState::ScanThing,
+                "SLOPE" => ss = ScanState::ScanSlope,
+                "TRANSFORM" => ss = ScanState::ScanTransform,
+                _ => {
+                    return Err(LevelError::UnknownSection {
+                        at: ctx.at(column + 1, section),
+                        message: format!("unknown section [{}]", section),
+                    })
+                }
+            }
+        } else {
+            let fields = ctx.fields();
+            match ss {
+                ScanState::ScanWall => {
+                    // ax ay bx by portal, optionally followed by the wall
+                    // texture and then the upper and lower step textures,
+                    // each a texture name or - for none, a wall texture of
+                    // `mirror` making the wall a mirror
+                    let expected = match fields.len() {
+                        0..=5 => 5,
+                        6 => 6,
+                        _ => 8,
+                    };
+                    ctx.check_count(&fields, "WALL", expected)?;
+                    let (texture, mirror) = match fields.get(5) {
+                        Some(&(_, name)) if name != "-" => level.wall_ref(name),
+                        _ => (TEXTURE_NON, false),
+                    };
+                    let mut step = |i: usize| match fields.get(i) {
+                        Some(&(_, name)) if name != "-" => level.texture_ref(name),
+                        _ => TEXTURE_NON,
+                    };
+                    let (upper, lower) = (step(6), step(7));
+
+                    level.walls.arr.push(Wall {
+                        a: V2i::new(
+                            ctx.parse(fields[0], "an integer coordinate")?,
+                            ctx.parse(fields[1], "an integer coordinate")?,
+                        ),
+                        b: V2i::new(
+                            ctx.parse(fields[2], "an integer coordinate")?,
+                            ctx.parse(fields[3], "an integer coordinate")?,
+                        ),
+                        portal: ctx.parse(fields[4], "a sector index")?,
+                        texture,
+                        upper,
+                        lower,
+                        transform: None,
+                        mirror,
+                    });
+                }
+                ScanState::ScanSector => {
+                    // id firstwall nwalls zfloor zceil, optionally followed
+                    // by the floor and ceiling textures and the light level,
+                    // a ceiling of `sky` opening the sector to the sky
+                    let expected = match fields.len() {
+                        0..=5 => 5,
+                        6 => 6,
+                        7 => 7,
+                        _ => 8,
+                    };
+                    ctx.check_count(&fields, "SECTOR", expected)?;
+                    let floor = match fields.get(5) {
+                        Some(&(_, name)) if name != "-" => level.texture_ref(name),
+                        _ => TEXTURE_NON,
+                    };
+                    let (ceil, sky) = match fields.get(6) {
+                        Some(&(_, name)) if name != "-" => level.ceiling_ref(name),
+                        _ => (TEXTURE_NON, false),
+                    };
+                    let light = match fields.get(7) {
+                        Some(&field) => ctx.parse(field, "a light level")?,
+                        None => LIGHT_FULL,
+                    };
+
+                    level.sectors.arr.push(Sector {
+                        id: ctx.parse(fields[0], "an integer sector id")?,
+                        firstwall: ctx.parse(fields[1], "a wall index")?,
+                        nwalls: ctx.parse(fields[2], "a wall count")?,
+                        zfloor: ctx.parse(fields[3], "a floor height")?,
+                        zceil: ctx.parse(fields[4], "a ceiling height")?,
+                        floor,
+                        ceil,
+                        light,
+                        sky,
+                        floor_slope: None,
+                        ceil_slope: None,
+                    });
+                }
+                ScanState::ScanPlayer => {
+                    // x y angle, optionally followed by the start sector
+                    ctx.check_count(&fields, "PLAYER", if fields.len() > 3 { 4 } else { 3 })?;
+
+                    level.player = Some(PlayerStart {
+                        pos: V2::new(
+                            ctx.parse(fields[0], "a coordinate")?,
+                            ctx.parse(fields[1], "a coordinate")?,
+                        ),
+                        angle: deg_2_rad(ctx.parse(fields[2], "an angle in degrees")?),
+                        sector: match fields.get(3) {
+                            Some(&field) => Some(ctx.parse(field, "a sector index")?),
+                            None => None,
+                        },
+                    });
+                }
+                ScanState::ScanTitle => {
+                    // the whole line, spaces and all
+                    level.title = Some(p.trim_end().to_string());
+                }
+                ScanState::ScanThing => {
+                    // name x y sprite, optionally followed by the height,
+                    // the sprite a texture name or - for none
+                    ctx.check_count(&fields, "THING", if fields.len() > 4 { 5 } else { 4 })?;
+
+                    let mut thing = Thing::new(fields[0].1.to_string());
+                    thing.pos = V2::new(
+                        ctx.parse(fields[1], "a coordinate")?,
+                        ctx.parse(fields[2], "a coordinate")?,
+                    );
+                    thing.sprite = match fields[3].1 {
+                        "-" => TEXTURE_NON,
+                        name => level.texture_ref(name),
+                    };
+                    if let Some(&field) = fields.get(4) {
+                        thing.height = ctx.parse(field, "a height")?;
+                    }
+                    level.things.push(thing);
+                }
+                ScanState::ScanSlope => {
+                    // sector floor|ceil wall delta, the wall counted from the
+                    // sector's first
+                    ctx.check_count(&fields, "SLOPE", 4)?;
+
+                    let sector: usize = ctx.parse(fields[0], "a sector index")?;
+                    let ceil = match fields[1] {
+                        (_, "floor") => false,
+                        (_, "ceil") => true,
+                        (column, token) => {
+                            return Err(LevelError::Syntax {
+                                at: ctx.at(column, token),
+                                message: format!("`{}` is not `floor` or `ceil`", token),
+                            })
+                        }
+                    };
+                    let slope = Slope {
+                        wall: ctx.parse(fields[2], "a wall index")?,
+                        delta: ctx.parse(fields[3], "a height change")?,
+                    };
+                    slopes.push((i + 1, sector, ceil, slope));
+                }
+                ScanState::ScanTransform => {
+                    // wall dx dy angle, turning by the angle in degrees and
+                    // then moving by dx dy into the sector behind the wall
+                    ctx.check_count(&fields, "TRANSFORM", 4)?;
+
+                    let wall: usize = ctx.parse(fields[0], "a wall index")?;
+                    let transform = PortalTransform {
+                        offset: V2::new(
+                            ctx.parse(fields[1], "a coordinate")?,
+                            ctx.parse(fields[2], "a coordinate")?,
+                        ),
+                        angle: deg_2_rad(ctx.parse(fields[3], "an angle in degrees")?),
+                    };
+                    transforms.push((i + 1, wall, transform));
+                }
+                ScanState::ScanNone => {
+                    return Err(LevelError::UnknownSection {
+                        at: ctx.at(column, fields[0].1),
+                        message:
+                            "data outside of a [SECTOR], [WALL], [SLOPE], [TRANSFORM], [PLAYER], \
+                              [THING] or [TITLE] section"
+                                .to_string(),
+                    })
+                }
+            }
+        }
+    }
+
+    for (line, sector, ceil, slope) in slopes {
+        let Some(sector) = level.sectors.arr.get_mut(sector).filter(|_| sector != 0) else {
+            return Err(LevelError::BadReference {
+                path: path.to_string(),
+                message: format!(
+                    "line {}: slope of sector {} which does not exist",
+                    line, sector
+                ),
+            });
+        };
+        match ceil {
+            true => sector.ceil_slope = Some(slope),
+            false => sector.floor_slope = Some(slope),
+        }
+    }
+
+    for (line, wall, transform) in transforms {
+        let Some(wall) = level.walls.arr.get_mut(wall) else {
+            return Err(LevelError::BadReference {
+                path: path.to_string(),
+                message: format!(
+                    "line {}: transform of wall {} which does not exist",
+                    line, wall
+                ),
+            });
+        };
+        wall.transform = Some(transform);
+    }
+
+    Ok(level)
+}
@@ -0,0 +1,620 @@
+use std::fmt;
+
+use super::portals::{check_portals, PortalProblem};
+use crate::res::util::constants::SECTOR_NON;
+use crate::res::util::kinds::{Sectors, V2i, Wall, Walls};
+
+/// A structural problem found in loaded level data.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LevelProblem {
+    /// `firstwall + nwalls` runs past the loaded walls
+    WallRange {
+        sector: usize,
+        firstwall: usize,
+        nwalls: usize,
+        walls: usize,
+    },
+    /// a sector needs at least three walls to enclose anything
+    TooFewWalls { sector: usize, nwalls: usize },
+    /// a portal points at a sector that doesn't exist
+    BadPortal {
+        sector: usize,
+        wall: usize,
+        portal: usize,
+    },
+    /// a wall has a transform but is not a portal
+    SolidTransform { sector: usize, wall: usize },
+    /// a wall is a mirror but also a portal, which it is drawn as
+    MirrorPortal { sector: usize, wall: usize },
+    /// a wall starts where it ends, leaving nothing to draw, reflect off or
+    /// slide along
+    ZeroLength { sector: usize, wall: usize, at: V2i },
+    /// no wall of the sector starts where this one ends
+    OpenLoop {
+        sector: usize,
+        wall: usize,
+        end: V2i,
+    },
+    /// the wall loop runs counter-clockwise (or encloses nothing), which
+    /// `point_in_sector` and the renderer's backface test both reject
+    Winding { sector: usize, area: f32 },
+    /// the walls close into more than one loop, as around a pillar, where
+    /// the renderer needs a single one
+    Loops { sector: usize, loops: usize },
+    /// the loop bends inwards at a corner, but `point_in_sector` and the
+    /// renderer only handle convex sectors
+    Concave { sector: usize, at: V2i },
+    /// the floor is not below the ceiling
+    Heights {
+        sector: usize,
+        zfloor: f32,
+        zceil: f32,
+    },
+    /// a slope is about a wall the sector doesn't have
+    SlopeWall {
+        sector: usize,
+        wall: usize,
+        nwalls: usize,
+    },
+    /// a sloped floor or ceiling meets or crosses the other at a corner
+    SlopeHeights {
+        sector: usize,
+        at: V2i,
+        zfloor: f32,
+        zceil: f32,
+    },
+    /// a portal is not matched by one leading back
+    Portal(PortalProblem),
+}
+
+impl fmt::Display for LevelProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LevelProblem::WallRange {
+                sector,
+                firstwall,
+                nwalls,
+                walls,
+            } => write!(
+                f,
+                "sector {}: walls {}..{} are out of range, only {} walls are loaded",
+                sector,
+                firstwall,
+                firstwall.saturating_add(*nwalls),
+                walls
+            ),
+            LevelProblem::TooFewWalls { sector, nwalls } => {
+                write!(
+                    f,
+                    "sector {}: has {} walls, needs at least 3",
+                    sector, nwalls
+                )
+            }
+            LevelProblem::BadPortal {
+                sector,
+                wall,
+                portal,
+            } => write!(
+                f,
+                "sector {}, wall {}: portal to sector {} which does not exist",
+                sector, wall, portal
+            ),
+            LevelProblem::SolidTransform { sector, wall } => write!(
+                f,
+                "sector {}, wall {}: has a transform but is not a portal",
+                sector, wall
+            ),
+            LevelProblem::MirrorPortal { sector, wall } => write!(
+                f,
+                "sector {}, wall {}: is a mirror but also a portal",
+                sector, wall
+            ),
+            LevelProblem::ZeroLength { sector, wall, at } => write!(
+                f,
+                "sector {}, wall {}: starts and ends at ({}, {}), it has no length",
+                sector, wall, at.x, at.y
+            ),
+            LevelProblem::OpenLoop { sector, wall, end } => write!(
+                f,
+                "sector {}, wall {}: no wall continues from ({}, {}), the loop is not closed",
+                sector, wall, end.x, end.y
+            ),
+            LevelProblem::Winding { sector, area } => write!(
+                f,
+                "sector {}: walls must wind clockwise, signed area is {}",
+                sector, area
+            ),
+            LevelProblem::Loops { sector, loops } => write!(
+                f,
+                "sector {}: walls form {} separate loops, it needs exactly one",
+                sector, loops
+            ),
+            LevelProblem::Concave { sector, at } => write!(
+                f,
+                "sector {}: bends inwards at ({}, {}), sectors must be convex",
+                sector, at.x, at.y
+            ),
+            LevelProblem::Heights {
+                sector,
+                zfloor,
+                zceil,
+            } => write!(
+                f,
+                "sector {}: floor {} is not below ceiling {}",
+                sector, zfloor, zceil
+            ),
+            LevelProblem::SlopeWall {
+                sector,
+                wall,
+                nwalls,
+            } => write!(
+                f,
+                "sector {}: sloped about wall {}, but it only has {} walls",
+                sector, wall, nwalls
+            ),
+            LevelProblem::SlopeHeights {
+                sector,
+                at,
+                zfloor,
+                zceil,
+            } => write!(
+                f,
+                "sector {}: at ({}, {}) the floor {} is not below the ceiling {}",
+                sector, at.x, at.y, zfloor, zceil
+            ),
+            LevelProblem::Portal(problem) => write!(f, "{}", problem),
+        }
+    }
+}
+
+/// `point_side` in exact integer arithmetic: positive when `p` is on the left
+/// of `a` -> `b`, outside of a clockwise sector.
+fn side(p: &V2i, a: &V2i, b: &V2i) -> i64 {
+    let (px, py) = ((p.x - a.x) as i64, (p.y - a.y) as i64);
+    let (dx, dy) = ((b.x - a.x) as i64, (b.y - a.y) as i64);
+    py * dx - px * dy
+}
+
+/// How many separate loops `walls` form, following each wall to one that
+/// starts where it ends.
+fn count_loops(walls: &[Wall]) -> usize {
+    let mut used = vec![false; walls.len()];
+    let mut loops = 0;
+
+    for first in 0..walls.len() {
+        if used[first] {
+            continue;
+        }
+
+        loops += 1;
+        used[first] = true;
+        let mut at = first;
+        while walls[at].b != walls[first].a {
+            let next = (0..walls.len()).find(|&j| !used[j] && walls[j].a == walls[at].b);
+            let Some(next) = next else {
+                break;
+            };
+            used[next] = true;
+            at = next;
+        }
+    }
+
+    loops
+}
+
+/// Checks loaded sectors and walls for anything that would trip up the
+/// renderer, returning every problem found. An empty list means the level is
+/// safe to render.
+pub fn validate_level(sectors: &Sectors, walls: &Walls) -> Vec<LevelProblem> {
+    let mut problems = vec![];
+
+    // sector 0 does not exist
+    for (i, sector) in sectors.arr.iter().enumerate().skip(1) {
+        if sector.zfloor >= sector.zceil {
+            problems.push(LevelProblem::Heights {
+                sector: i,
+                zfloor: sector.zfloor,
+                zceil: sector.zceil,
+            });
+        }
+
+        let end = sector.firstwall.checked_add(sector.nwalls);
+        if end.is_none_or(|end| end > walls.len()) {
+            problems.push(LevelProblem::WallRange {
+                sector: i,
+                firstwall: sector.firstwall,
+                nwalls: sector.nwalls,
+                walls: walls.len(),
+            });
+            continue;
+        }
+
+        if sector.nwalls < 3 {
+            problems.push(LevelProblem::TooFewWalls {
+                sector: i,
+                nwalls: sector.nwalls,
+            });
+            continue;
+        }
+
+        let loop_walls = &walls.arr[sector.firstwall..sector.firstwall + sector.nwalls];
+
+        for (j, wall) in loop_walls.iter().enumerate() {
+            let wall_index = sector.firstwall + j;
+
+            if wall.portal as i32 != SECTOR_NON && wall.portal >= sectors.len() {
+                problems.push(LevelProblem::BadPortal {
+                    sector: i,
+                    wall: wall_index,
+                    portal: wall.portal,
+                });
+            }
+
+            if wall.transform.is_some() && wall.portal as i32 == SECTOR_NON {
+                problems.push(LevelProblem::SolidTransform {
+                    sector: i,
+                    wall: wall_index,
+                });
+            }
+
+            if wall.mirror && wall.portal as i32 != SECTOR_NON {
+                problems.push(LevelProblem::MirrorPortal {
+                    sector: i,
+                    wall: wall_index,
+                });
+            }
+
+            if wall.a == wall.b {
+                problems.push(LevelProblem::ZeroLength {
+                    sector: i,
+                    wall: wall_index,
+                    at: wall.a.clone(),
+                });
+            }
+
+            // walls may be listed in either order, they only have to connect
+            if !loop_walls.iter().any(|next| next.a == wall.b) {
+                problems.push(LevelProblem::OpenLoop {
+                    sector: i,
+                    wall: wall_index,
+                    end: wall.b.clone(),
+                });
+            }
+        }
+
+        // shoelace formula, negative when clockwise
+        let area = loop_walls
+            .iter()
+            .map(|w| (w.a.x as f32 * w.b.y as f32) - (w.a.y as f32 * w.b.x as f32))
+            .sum::<f32>()
+            / 2.0;
+
+        let closed = !problems
+            .iter()
+            .any(|p| matches!(p, LevelProblem::OpenLoop { sector, .. } if *sector == i));
+        let loops = count_loops(loop_walls);
+
+        if closed && loops > 1 {
+            problems.push(LevelProblem::Loops { sector: i, loops });
+        } else if area >= 0.0 {
+            problems.push(LevelProblem::Winding { sector: i, area });
+        } else if closed {
+            // turning left anywhere along a clockwise loop is a dent
+            for wall in loop_walls {
+                let turns_left = loop_walls
+                    .iter()
+                    .filter(|next| next.a == wall.b)
+                    .any(|next| side(&next.b, &wall.a, &wall.b) > 0);
+                if turns_left {
+                    problems.push(LevelProblem::Concave {
+                        sector: i,
+                        at: wall.b.clone(),
+                    });
+                }
+            }
+        }
+
+        let slopes = [&sector.floor_slope, &sector.ceil_slope];
+        let bad_slopes: Vec<_> = slopes
+            .into_iter()
+            .flatten()
+            .filter(|slope| slope.wall >= sector.nwalls)
+            .collect();
+        for slope in &bad_slopes {
+            problems.push(LevelProblem::SlopeWall {
+                sector: i,
+                wall: slope.wall,
+                nwalls: sector.nwalls,
+            });
+        }
+
+        // planes are straight, so if they clear each other at every corner
+        // they do everywhere in between
+        if bad_slopes.is_empty() && slopes.iter().any(|slope| slope.is_some()) {
+            for wall in loop_walls {
+                let p = wall.a.clone().v2i_to_v2();
+                let (zfloor, zceil) = (sector.floor_at(walls, &p), sector.ceil_at(walls, &p));
+                if zfloor >= zceil {
+                    problems.push(LevelProblem::SlopeHeights {
+                        sector: i,
+                        at: wall.a.clone(),
+                        zfloor,
+                        zceil,
+                    });
+                }
+            }
+        }
+    }
+
+    problems.extend(
+        check_portals(sectors, walls)
+            .into_iter()
+            .map(LevelProblem::Portal),
+    );
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::res::util::kinds::{PortalTransform, Sector, Slope, Wall, V2};
+
+    /// Walls running around `corners` in order.
+    fn outline(corners: &[(i32, i32)]) -> Vec<Wall> {
+        (0..corners.len())
+            .map(|i| {
+                let (a, b) = (corners[i], corners[(i + 1) % corners.len()]);
+                Wall {
+                    a: V2i::new(a.0, a.1),
+                    b: V2i::new(b.0, b.1),
+                    ..Default::default()
+                }
+            })
+            .collect()
+    }
+
+    /// A 4x4 square, wound clockwise.
+    fn square(firstwall: usize) -> (Sector, Vec<Wall>) {
+        let walls = outline(&[(0, 0), (0, 4), (4, 4), (4, 0)]);
+        let sector = Sector {
+            id: 1,
+            firstwall,
+            nwalls: 4,
+            zfloor: 0.0,
+            zceil: 4.0,
+            ..Default::default()
+        };
+        (sector, walls)
+    }
+
+    /// Validates a single square sector after letting `edit` break it.
+    fn check(edit: impl FnOnce(&mut Sector, &mut Vec<Wall>)) -> Vec<LevelProblem> {
+        let (mut sector, mut arr) = square(0);
+        edit(&mut sector, &mut arr);
+        let mut sectors = Sectors::new();
+        sectors.arr.push(sector);
+        validate_level(&sectors, &Walls { arr })
+    }
+
+    #[test]
+    fn square_is_valid() {
+        assert_eq!(check(|_, _| {}), vec![]);
+    }
+
+    #[test]
+    fn wall_range() {
+        assert_eq!(
+            check(|s, _| s.firstwall = 2),
+            vec![LevelProblem::WallRange {
+                sector: 1,
+                firstwall: 2,
+                nwalls: 4,
+                walls: 4,
+            }]
+        );
+    }
+
+    #[test]
+    fn wall_range_overflow() {
+        let problems = check(|s, _| s.firstwall = usize::MAX);
+        assert_eq!(
+            problems,
+            vec![LevelProblem::WallRange {
+                sector: 1,
+                firstwall: usize::MAX,
+                nwalls: 4,
+                walls: 4,
+            }]
+        );
+        // the message must not overflow either
+        assert!(problems[0].to_string().starts_with("sector 1: walls "));
+    }
+
+    #[test]
+    fn too_few_walls() {
+        assert_eq!(
+            check(|s, _| s.nwalls = 2),
+            vec![LevelProblem::TooFewWalls {
+                sector: 1,
+                nwalls: 2
+            }]
+        );
+    }
+
+    #[test]
+    fn bad_portal() {
+        assert_eq!(
+            check(|_, w| w[1].portal = 5),
+            vec![LevelProblem::BadPortal {
+                sector: 1,
+                wall: 1,
+                portal: 5,
+            }]
+        );
+    }
+
+    #[test]
+    fn portal() {
+        // the sector has no reversed wall 1 to lead back through
+        assert_eq!(
+            check(|_, w| w[1].portal = 1),
+            vec![LevelProblem::Portal(PortalProblem::MismatchedEndpoints {
+                sector: 1,
+                wall: 1,
+                target: 1,
+                back: 1,
+            })]
+        );
+    }
+
+    #[test]
+    fn solid_transform() {
+        assert_eq!(
+            check(|_, w| {
+                w[2].transform = Some(PortalTransform {
+                    offset: V2::new(8.0, 0.0),
+                    angle: 0.0,
+                })
+            }),
+            vec![LevelProblem::SolidTransform { sector: 1, wall: 2 }]
+        );
+    }
+
+    #[test]
+    fn mirror_portal() {
+        let problems = check(|_, w| {
+            w[3].mirror = true;
+            w[3].portal = 1;
+        });
+        assert_eq!(
+            problems[0],
+            LevelProblem::MirrorPortal { sector: 1, wall: 3 }
+        );
+    }
+
+    #[test]
+    fn zero_length() {
+        let problems = check(|s, w| {
+            *w = outline(&[(0, 0), (0, 4), (4, 4), (4, 4), (4, 0)]);
+            s.nwalls = w.len();
+        });
+        assert_eq!(
+            problems[0],
+            LevelProblem::ZeroLength {
+                sector: 1,
+                wall: 2,
+                at: V2i::new(4, 4),
+            }
+        );
+        assert_eq!(
+            problems[0].to_string(),
+            "sector 1, wall 2: starts and ends at (4, 4), it has no length"
+        );
+    }
+
+    #[test]
+    fn open_loop() {
+        assert_eq!(
+            check(|_, w| w[2].b = V2i::new(4, 1)),
+            vec![LevelProblem::OpenLoop {
+                sector: 1,
+                wall: 2,
+                end: V2i::new(4, 1),
+            }]
+        );
+    }
+
+    #[test]
+    fn winding() {
+        let problems = check(|_, w| {
+            w.reverse();
+            for wall in w.iter_mut() {
+                std::mem::swap(&mut wall.a, &mut wall.b);
+            }
+        });
+        assert_eq!(
+            problems,
+            vec![LevelProblem::Winding {
+                sector: 1,
+                area: 16.0
+            }]
+        );
+    }
+
+    #[test]
+    fn loops() {
+        // a pillar in the middle, wound the other way
+        let problems = check(|s, w| {
+            w.extend(outline(&[(1, 1), (2, 1), (2, 2), (1, 2)]));
+            s.nwalls = w.len();
+        });
+        assert_eq!(
+            problems,
+            vec![LevelProblem::Loops {
+                sector: 1,
+                loops: 2
+            }]
+        );
+    }
+
+    #[test]
+    fn concave() {
+        let problems = check(|s, w| {
+            *w = outline(&[(0, 0), (0, 8), (4, 8), (4, 4), (8, 4), (8, 0)]);
+            s.nwalls = w.len();
+        });
+        assert_eq!(
+            problems,
+            vec![LevelProblem::Concave {
+                sector: 1,
+                at: V2i::new(4, 4),
+            }]
+        );
+    }
+
+    #[test]
+    fn heights() {
+        assert_eq!(
+            check(|s, _| s.zfloor = 4.0),
+            vec![LevelProblem::Heights {
+                sector: 1,
+                zfloor: 4.0,
+                zceil: 4.0,
+            }]
+        );
+    }
+
+    #[test]
+    fn slope_wall() {
+        assert_eq!(
+            check(|s, _| s.ceil_slope = Some(Slope {
+                wall: 4,
+                delta: 1.0
+            })),
+            vec![LevelProblem::SlopeWall {
+                sector: 1,
+                wall: 4,
+                nwalls: 4,
+            }]
+        );
+    }
+
+    #[test]
+    fn slope_heights() {
+        // rises 8 over the 4 units away from the first wall, through the
+        // ceiling at the far corners
+        let problems = check(|s, _| {
+            s.floor_slope = Some(Slope {
+                wall: 0,
+                delta: 2.0,
+            })
+        });
+        assert_eq!(problems.len(), 2);
+        for problem in &problems {
+            assert!(matches!(
+                problem,
+                LevelProblem::SlopeHeights { sector: 1, at, .. } if at.x == 4
+            ));
+        }
+    }
+}
@@ -0,0 +1,741 @@
+use std::collections::HashMap;
+
+use super::convex::{convex_pieces, Corner};
+use super::error::LevelError;
+use super::Level;
+use crate::res::util::constants::{LIGHT_FULL, TEXTURE_NON};
+use crate::res::util::kinds::{Sector, Sectors, V2i, Wall, Walls};
+
+/// File extension of Doom WADs.
+pub const EXTENSION: &str = "wad";
+
+/// Doom's eye height in map units, which lines up with `EYE_Z`.
+pub const DOOM_EYE_Z: f32 = 41.0;
+
+const NO_SIDEDEF: u16 = 0xFFFF;
+
+/// The ceiling flat doom draws as sky.
+const SKY_FLAT: &str = "F_SKY1";
+
+const LINEDEF_SIZE: usize = 14;
+const SIDEDEF_SIZE: usize = 30;
+const SECTOR_SIZE: usize = 26;
+
+/// Lumps that may follow a map marker; the marker is found as the lump right
+/// before THINGS.
+const MAP_LUMPS: [&str; 10] = [
+    "THINGS", "LINEDEFS", "SIDEDEFS", "VERTEXES", "SEGS", "SSECTORS", "NODES", "SECTORS", "REJECT",
+    "BLOCKMAP",
+];
+
+struct Lump<'a> {
+    name: String,
+    data: &'a [u8],
+}
+
+/// A linedef side as seen from one sector, running with the sector on its
+/// right like zengine walls.
+struct Edge {
+    a: (i16, i16),
+    b: (i16, i16),
+    other: Option<usize>,
+    side: usize,
+}
+
+/// The parts of a sidedef zengine uses.
+struct Side {
+    upper: String,
+    lower: String,
+    middle: String,
+    sector: usize,
+}
+
+/// The parts of a doom sector zengine uses.
+struct DoomSector {
+    floor: i16,
+    ceil: i16,
+    floor_flat: String,
+    ceil_flat: String,
+    light: u16,
+}
+
+fn corrupt(path: &str, message: String) -> LevelError {
+    LevelError::Corrupt {
+        path: path.to_string(),
+        message,
+    }
+}
+
+fn i16_at(data: &[u8], offset: usize) -> i16 {
+    i16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+/// A NUL padded 8 character lump or texture name.
+fn name_at(data: &[u8], offset: usize) -> String {
+    data[offset..offset + 8]
+        .iter()
+        .take_while(|&&c| c != 0)
+        .map(|&c| (c as char).to_ascii_uppercase())
+        .collect()
+}
+
+fn read_lumps<'a>(path: &str, bytes: &'a [u8]) -> Result<Vec<Lump<'a>>, LevelError> {
+    if bytes.len() < 12 || !(&bytes[..4] == b"IWAD" || &bytes[..4] == b"PWAD") {
+        return Err(corrupt(path, "not a WAD file".to_string()));
+    }
+
+    let count = u32::from_le_bytes(bytes[4..8].try_into().unwrap()) as usize;
+    let dir = u32::from_le_bytes(bytes[8..12].try_into().unwrap()) as usize;
+
+    let mut lumps = Vec::with_capacity(count);
+    for i in 0..count {
+        let entry = dir + i * 16;
+        let Some(raw) = bytes.get(entry..entry + 16) else {
+            return Err(corrupt(
+                path,
+                "lump directory runs past the end of the file".to_string(),
+            ));
+        };
+
+        let pos = u32::from_le_bytes(raw[0..4].try_into().unwrap()) as usize;
+        let size = u32::from_le_bytes(raw[4..8].try_into().unwrap()) as usize;
+        let name = name_at(raw, 8);
+
+        let Some(data) = bytes.get(pos..pos + size) else {
+            return Err(corrupt(
+                path,
+                format!("lump {} runs past the end of the file", name),
+            ));
+        };
+
+        lumps.push(Lump { name, data });
+    }
+
+    Ok(lumps)
+}
+
+/// Names of the maps in a WAD, in directory order.
+pub fn wad_maps(path: &str) -> Result<Vec<String>, LevelError> {
+    let bytes = std::fs::read(path).map_err(|error| LevelError::open(path, error))?;
+    let lumps = read_lumps(path, &bytes)?;
+
+    Ok(lumps
+        .windows(2)
+        .filter(|pair| pair[1].name == "THINGS")
+        .map(|pair| pair[0].name.clone())
+        .collect())
+}
+
+/// Imports a Doom map (`E1M1`, `MAP01`, ...) from a WAD, or the first map if
+/// `map` is `None`. Heights are multiplied by `height_scale`, `EYE_Z /
+/// DOOM_EYE_Z` putting the eye where Doom's is. Vertices stay in map units,
+/// as wall vertices are integers and scaling them down would snap details
+/// like stair steps together. Walls keep their sidedef's texture names,
+/// which only draw if textures by those names are loaded. Doom sectors that
+/// are concave or go round pillars are split into convex pieces, each a
+/// sector of its own joined to the others by portals.
+pub fn import_wad(path: &str, map: Option<&str>, height_scale: f32) -> Result<Level, LevelError> {
+    let bytes = std::fs::read(path).map_err(|error| LevelError::open(path, error))?;
+    let lumps = read_lumps(path, &bytes)?;
+
+    let marker = lumps
+        .windows(2)
+        .position(|pair| {
+            pair[1].name == "THINGS" && map.is_none_or(|map| pair[0].name.eq_ignore_ascii_case(map))
+        })
+        .ok_or_else(|| match map {
+            Some(map) => corrupt(path, format!("no map {} in WAD", map)),
+            None => corrupt(path, "no maps in WAD".to_string()),
+        })?;
+    let map_name = &lumps[marker].name;
+
+    let lump = |name: &str| -> Result<&[u8], LevelError> {
+        lumps[marker + 1..]
+            .iter()
+            .take_while(|lump| MAP_LUMPS.contains(&lump.name.as_str()))
+            .find(|lump| lump.name == name)
+            .map(|lump| lump.data)
+            .ok_or_else(|| corrupt(path, format!("map {} has no {} lump", map_name, name)))
+    };
+
+    let vertexes: Vec<(i16, i16)> = lump("VERTEXES")?
+        .chunks_exact(4)
+        .map(|v| (i16_at(v, 0), i16_at(v, 2)))
+        .collect();
+    let sides: Vec<Side> = lump("SIDEDEFS")?
+        .chunks_exact(SIDEDEF_SIZE)
+        .map(|s| Side {
+            upper: name_at(s, 4),
+            lower: name_at(s, 12),
+            middle: name_at(s, 20),
+            sector: u16_at(s, 28) as usize,
+        })
+        .collect();
+    let doom_sectors: Vec<DoomSector> = lump("SECTORS")?
+        .chunks_exact(SECTOR_SIZE)
+        .map(|s| DoomSector {
+            floor: i16_at(s, 0),
+            ceil: i16_at(s, 2),
+            floor_flat: name_at(s, 4),
+            ceil_flat: name_at(s, 12),
+            light: u16_at(s, 20),
+        })
+        .collect();
+
+    let side_sector = |side: u16| -> Result<Option<(usize, usize)>, LevelError> {
+        if side == NO_SIDEDEF {
+            return Ok(None);
+        }
+        match sides.get(side as usize) {
+            Some(s) if s.sector < doom_sectors.len() => Ok(Some((s.sector, side as usize))),
+            Some(s) => Err(corrupt(
+                path,
+                format!("sidedef {} has bad sector {}", side, s.sector),
+            )),
+            None => Err(corrupt(
+                path,
+                format!("linedef uses missing sidedef {}", side),
+            )),
+        }
+    };
+
+    let mut edges: Vec<Vec<Edge>> = (0..doom_sectors.len()).map(|_| vec![]).collect();
+
+    for (i, line) in lump("LINEDEFS")?.chunks_exact(LINEDEF_SIZE).enumerate() {
+        let (Some(&v1), Some(&v2)) = (
+            vertexes.get(u16_at(line, 0) as usize),
+            vertexes.get(u16_at(line, 2) as usize),
+        ) else {
+            return Err(corrupt(
+                path,
+                format!("linedef {} uses a missing vertex", i),
+            ));
+        };
+
+        let front = side_sector(u16_at(line, 10))?;
+        let back = side_sector(u16_at(line, 12))?;
+
+        // both sides in one sector only decorates, it doesn't bound anything
+        if front.map(|(sector, _)| sector) == back.map(|(sector, _)| sector) {
+            continue;
+        }
+
+        // the front side is on the right of v1 -> v2
+        if let Some((sector, side)) = front {
+            edges[sector].push(Edge {
+                a: v1,
+                b: v2,
+                other: back.map(|(sector, _)| sector),
+                side,
+            });
+        }
+
+        if let Some((sector, side)) = back {
+            edges[sector].push(Edge {
+                a: v2,
+                b: v1,
+                other: front.map(|(sector, _)| sector),
+                side,
+            });
+        }
+    }
+
+    let mut level = Level {
+        title: Some(map_name.clone()),
+        ..Default::default()
+    };
+
+    // "-" is doom's "no texture"
+    let mut texture = |name: &str| match name {
+        "" | "-" => TEXTURE_NON,
+        name => level.texture_ref(name),
+    };
+
+    // zengine sectors have to be convex and bounded by a single loop, so
+    // doom sectors are split into convex pieces, each its own sector
+    let mut pieces: Vec<(usize, Vec<Corner<&Edge>>)> = vec![];
+    for (i, sector_edges) in edges.iter().enumerate() {
+        let loops = trace_loops(path, i, sector_edges)?
+            .into_iter()
+            .map(|edges| {
+                edges
+                    .into_iter()
+                    .filter(|edge| edge.a != edge.b)
+                    .map(|edge| (V2i::new(edge.a.0 as i32, edge.a.1 as i32), Some(edge)))
+                    .collect()
+            })
+            .collect();
+
+        let split = convex_pieces(loops).ok_or_else(|| LevelError::Unsupported {
+            path: path.to_string(),
+            message: format!(
+                "map {} sector {} can't be split into convex pieces, its linedefs cross",
+                map_name, i
+            ),
+        })?;
+        pieces.extend(split.into_iter().map(|piece| (i, piece)));
+    }
+
+    // zengine sector 0 does not exist, so pieces are numbered from 1
+    let mut owners: HashMap<(V2i, V2i), usize> = HashMap::new();
+    for (n, (_, piece)) in pieces.iter().enumerate() {
+        for k in 0..piece.len() {
+            let (a, b) = (&piece[k].0, &piece[(k + 1) % piece.len()].0);
+            owners.insert((a.clone(), b.clone()), n + 1);
+        }
+    }
+
+    let mut sectors = Sectors::new();
+    let mut walls = Walls::new();
+
+    for (n, (i, piece)) in pieces.iter().enumerate() {
+        let firstwall = walls.len();
+
+        for k in 0..piece.len() {
+            let (a, b) = (piece[k].0.clone(), piece[(k + 1) % piece.len()].0.clone());
+            let twin = owners.get(&(b.clone(), a.clone())).copied();
+
+            let wall = match piece[k].1 {
+                // an edge between two pieces of the same doom sector, which
+                // can't be seen
+                None => Wall {
+                    portal: twin.unwrap_or(0),
+                    ..Default::default()
+                },
+                Some(edge) => {
+                    let side = &sides[edge.side];
+                    Wall {
+                        portal: match edge.other {
+                            Some(_) => twin.unwrap_or(0),
+                            None => 0,
+                        },
+                        // a two sided middle texture is see-through decoration
+                        texture: match edge.other {
+                            Some(_) => TEXTURE_NON,
+                            None => texture(&side.middle),
+                        },
+                        upper: texture(&side.upper),
+                        lower: texture(&side.lower),
+                        ..Default::default()
+                    }
+                }
+            };
+            walls.arr.push(Wall { a, b, ..wall });
+        }
+
+        let doom_sector = &doom_sectors[*i];
+        let sky = doom_sector.ceil_flat == SKY_FLAT;
+        sectors.arr.push(Sector {
+            id: (n + 1) as i32,
+            firstwall,
+            nwalls: walls.len() - firstwall,
+            zfloor: doom_sector.floor as f32 * height_scale,
+            zceil: doom_sector.ceil as f32 * height_scale,
+            floor: texture(&doom_sector.floor_flat),
+            ceil: match sky {
+                true => TEXTURE_NON,
+                false => texture(&doom_sector.ceil_flat),
+            },
+            // doom light levels run 0 to 255
+            light: f32::min(doom_sector.light as f32 / 255.0, 1.0) * LIGHT_FULL,
+            sky,
+            // doom floors and ceilings are all flat
+            floor_slope: None,
+            ceil_slope: None,
+        });
+    }
+
+    level.sectors = sectors;
+    level.walls = walls;
+    Ok(level)
+}
+
+/// Orders a sector's edges into closed loops, each edge starting where the
+/// previous one ended.
+fn trace_loops<'a>(
+    path: &str,
+    sector: usize,
+    edges: &'a [Edge],
+) -> Result<Vec<Vec<&'a Edge>>, LevelError> {
+    let mut starts: HashMap<(i16, i16), Vec<usize>> = HashMap::new();
+    for (i, edge) in edges.iter().enumerate() {
+        starts.entry(edge.a).or_default().push(i);
+    }
+
+    let mut used = vec![false; edges.len()];
+    let mut loops = vec![];
+
+    for first in 0..edges.len() {
+        if used[first] {
+            continue;
+        }
+
+        used[first] = true;
+        let mut ordered = vec![&edges[first]];
+
+        let mut at = edges[first].b;
+        while at != edges[first].a {
+            let next = starts
+                .get(&at)
+                .and_then(|candidates| candidates.iter().copied().find(|&j| !used[j]))
+                .ok_or_else(|| {
+                    corrupt(
+                        path,
+                        format!(
+                            "sector {}: linedefs do not close into a loop at ({}, {})",
+                            sector, at.0, at.1
+                        ),
+                    )
+                })?;
+
+            used[next] = true;
+            ordered.push(&edges[next]);
+            at = edges[next].b;
+        }
+        loops.push(ordered);
+    }
+
+    Ok(loops)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::res::level::read_level;
+    use crate::res::level::validate::validate_level;
+    use crate::res::testing::TempFile;
+    use crate::res::util::constants::{EYE_Z, STEP_HEIGHT};
+
+    /// An 8 byte, NUL padded name.
+    fn name(name: &str) -> Vec<u8> {
+        let mut bytes = name.as_bytes().to_vec();
+        bytes.resize(8, 0);
+        bytes
+    }
+
+    fn vertexes(points: &[(i16, i16)]) -> Vec<u8> {
+        points
+            .iter()
+            .flat_map(|&(x, y)| [x.to_le_bytes(), y.to_le_bytes()].concat())
+            .collect()
+    }
+
+    /// `(v1, v2, front, back)` with `NO_SIDEDEF` for no back.
+    fn linedefs(lines: &[(u16, u16, u16, u16)]) -> Vec<u8> {
+        lines
+            .iter()
+            .flat_map(|&(v1, v2, front, back)| {
+                [v1, v2, 0, 0, 0, front, back]
+                    .iter()
+                    .flat_map(|field| field.to_le_bytes())
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    /// `(middle texture, sector)`
+    fn sidedefs(sides: &[(&str, u16)]) -> Vec<u8> {
+        sides
+            .iter()
+            .flat_map(|&(middle, sector)| {
+                [
+                    vec![0; 4],
+                    name("-"),
+                    name("-"),
+                    name(middle),
+                    sector.to_le_bytes().to_vec(),
+                ]
+                .concat()
+            })
+            .collect()
+    }
+
+    /// `(floor, ceiling, ceiling flat)`
+    fn sectors(sectors: &[(i16, i16, &str)]) -> Vec<u8> {
+        sectors
+            .iter()
+            .flat_map(|&(floor, ceil, ceil_flat)| {
+                [
+                    floor.to_le_bytes().to_vec(),
+                    ceil.to_le_bytes().to_vec(),
+                    name("FLOOR4_8"),
+                    name(ceil_flat),
+                    160u16.to_le_bytes().to_vec(),
+                    vec![0; 4],
+                ]
+                .concat()
+            })
+            .collect()
+    }
+
+    fn wad(lumps: &[(&str, Vec<u8>)]) -> Vec<u8> {
+        let mut data = vec![];
+        let mut dir = vec![];
+        for (lump, bytes) in lumps {
+            dir.extend(((12 + data.len()) as u32).to_le_bytes());
+            dir.extend((bytes.len() as u32).to_le_bytes());
+            dir.extend(name(lump));
+            data.extend(bytes);
+        }
+
+        let header = [
+            b"PWAD".to_vec(),
+            (lumps.len() as u32).to_le_bytes().to_vec(),
+            ((12 + data.len()) as u32).to_le_bytes().to_vec(),
+        ];
+        [header.concat(), data, dir].concat()
+    }
+
+    /// Two 64 unit rooms side by side joined by a two sided line, the
+    /// second open to the sky and a step up.
+    fn two_rooms() -> Vec<(&'static str, Vec<u8>)> {
+        vec![
+            ("MAP01", vec![]),
+            ("THINGS", vec![]),
+            (
+                "LINEDEFS",
+                linedefs(&[
+                    (0, 1, 0, NO_SIDEDEF),
+                    (1, 2, 0, NO_SIDEDEF),
+                    (3, 0, 0, NO_SIDEDEF),
+                    (2, 3, 0, 1),
+                    (2, 4, 1, NO_SIDEDEF),
+                    (4, 5, 1, NO_SIDEDEF),
+                    (5, 3, 1, NO_SIDEDEF),
+                ]),
+            ),
+            ("SIDEDEFS", sidedefs(&[("STARTAN3", 0), ("STARTAN3", 1)])),
+            (
+                "VERTEXES",
+                vertexes(&[(0, 0), (0, 64), (64, 64), (64, 0), (128, 64), (128, 0)]),
+            ),
+            (
+                "SECTORS",
+                sectors(&[(0, 128, "CEIL3_5"), (16, 128, SKY_FLAT)]),
+            ),
+        ]
+    }
+
+    fn import(lumps: &[(&str, Vec<u8>)]) -> Result<Level, LevelError> {
+        let file = TempFile::new("map.wad");
+        std::fs::write(file.path(), wad(lumps)).unwrap();
+        import_wad(file.path(), None, 1.0)
+    }
+
+    fn corruption(lumps: &[(&str, Vec<u8>)]) -> String {
+        match import(lumps) {
+            Err(LevelError::Corrupt { message, .. }) => message,
+            other => panic!("expected a corrupt WAD, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn two_sectors() {
+        let level = import(&two_rooms()).unwrap();
+
+        assert_eq!(level.title.as_deref(), Some("MAP01"));
+        assert_eq!(level.sectors.len(), 3);
+        assert_eq!(level.walls.len(), 8);
+        assert_eq!(validate_level(&level.sectors, &level.walls), vec![]);
+
+        let portals: Vec<_> = level.walls.arr.iter().map(|w| w.portal).collect();
+        assert_eq!(portals, vec![0, 0, 2, 0, 1, 0, 0, 0]);
+
+        let step = &level.sectors.arr[2];
+        assert_eq!((step.zfloor, step.zceil, step.sky), (16.0, 128.0, true));
+        assert_eq!(level.textures, vec!["STARTAN3", "FLOOR4_8", "CEIL3_5"]);
+    }
+
+    #[test]
+    fn missing_lump() {
+        let mut lumps = two_rooms();
+        lumps.retain(|(name, _)| *name != "SECTORS");
+        assert_eq!(corruption(&lumps), "map MAP01 has no SECTORS lump");
+    }
+
+    #[test]
+    fn bad_sidedef() {
+        let mut lumps = two_rooms();
+        lumps[2].1 = linedefs(&[(0, 1, 7, NO_SIDEDEF)]);
+        assert_eq!(corruption(&lumps), "linedef uses missing sidedef 7");
+
+        let mut lumps = two_rooms();
+        lumps[3].1 = sidedefs(&[("STARTAN3", 0), ("STARTAN3", 9)]);
+        assert_eq!(corruption(&lumps), "sidedef 1 has bad sector 9");
+    }
+
+    #[test]
+    fn unclosed_loop() {
+        // drop the second room's south wall
+        let mut lumps = two_rooms();
+        lumps[2].1.truncate(6 * LINEDEF_SIZE);
+        assert_eq!(
+            corruption(&lumps),
+            "sector 1: linedefs do not close into a loop at (128, 0)"
+        );
+    }
+
+    /// The level is fit to draw, its portals included.
+    fn check(level: &Level) {
+        assert_eq!(validate_level(&level.sectors, &level.walls), vec![]);
+    }
+
+    #[test]
+    fn concave_sector() {
+        // an L of one sector
+        let mut lumps = two_rooms();
+        lumps[2].1 = linedefs(&[
+            (0, 1, 0, NO_SIDEDEF),
+            (1, 2, 0, NO_SIDEDEF),
+            (2, 3, 0, NO_SIDEDEF),
+            (3, 4, 0, NO_SIDEDEF),
+            (4, 5, 0, NO_SIDEDEF),
+            (5, 0, 0, NO_SIDEDEF),
+        ]);
+        lumps[4].1 = vertexes(&[(0, 0), (0, 128), (64, 128), (64, 64), (128, 64), (128, 0)]);
+        lumps[5].1 = sectors(&[(0, 128, "CEIL3_5")]);
+
+        let level = import(&lumps).unwrap();
+        check(&level);
+        assert_eq!(level.sectors.len(), 3);
+        // the six linedefs, and the edge between the two pieces both ways
+        assert_eq!(level.walls.len(), 8);
+        let inner = level.walls.arr.iter().filter(|w| w.portal != 0).count();
+        assert_eq!(inner, 2);
+    }
+
+    #[test]
+    fn sector_round_a_pillar() {
+        let lumps = vec![
+            ("MAP01", vec![]),
+            ("THINGS", vec![]),
+            (
+                "LINEDEFS",
+                linedefs(&[
+                    (0, 1, 0, NO_SIDEDEF),
+                    (1, 2, 0, NO_SIDEDEF),
+                    (2, 3, 0, NO_SIDEDEF),
+                    (3, 0, 0, NO_SIDEDEF),
+                    (4, 5, 0, NO_SIDEDEF),
+                    (5, 6, 0, NO_SIDEDEF),
+                    (6, 7, 0, NO_SIDEDEF),
+                    (7, 4, 0, NO_SIDEDEF),
+                ]),
+            ),
+            ("SIDEDEFS", sidedefs(&[("STARTAN3", 0)])),
+            (
+                "VERTEXES",
+                vertexes(&[
+                    (0, 0),
+                    (0, 192),
+                    (192, 192),
+                    (192, 0),
+                    (64, 64),
+                    (128, 64),
+                    (128, 128),
+                    (64, 128),
+                ]),
+            ),
+            ("SECTORS", sectors(&[(0, 128, "CEIL3_5")])),
+        ];
+
+        let level = import(&lumps).unwrap();
+        check(&level);
+        assert!(level.sectors.len() > 2);
+        let solid = level.walls.arr.iter().filter(|w| w.portal == 0).count();
+        assert_eq!(solid, 8);
+    }
+
+    #[test]
+    fn stairs_at_the_default_scale() {
+        // a room and two 16 unit deep steps up, 16 units each
+        let lumps = vec![
+            ("MAP01", vec![]),
+            ("THINGS", vec![]),
+            (
+                "LINEDEFS",
+                linedefs(&[
+                    (0, 1, 0, NO_SIDEDEF),
+                    (1, 2, 0, NO_SIDEDEF),
+                    (3, 0, 0, NO_SIDEDEF),
+                    (2, 3, 0, 1),
+                    (2, 4, 1, NO_SIDEDEF),
+                    (5, 3, 1, NO_SIDEDEF),
+                    (4, 5, 1, 2),
+                    (4, 6, 2, NO_SIDEDEF),
+                    (6, 7, 2, NO_SIDEDEF),
+                    (7, 5, 2, NO_SIDEDEF),
+                ]),
+            ),
+            (
+                "SIDEDEFS",
+                sidedefs(&[("STARTAN3", 0), ("STARTAN3", 1), ("STARTAN3", 2)]),
+            ),
+            (
+                "VERTEXES",
+                vertexes(&[
+                    (0, 0),
+                    (0, 64),
+                    (64, 64),
+                    (64, 0),
+                    (80, 64),
+                    (80, 0),
+                    (96, 64),
+                    (96, 0),
+                ]),
+            ),
+            (
+                "SECTORS",
+                sectors(&[
+                    (0, 128, "CEIL3_5"),
+                    (16, 128, "CEIL3_5"),
+                    (32, 128, "CEIL3_5"),
+                ]),
+            ),
+        ];
+        let file = TempFile::new("stairs.wad");
+        std::fs::write(file.path(), wad(&lumps)).unwrap();
+
+        let level = read_level(file.path()).unwrap();
+        check(&level);
+        assert_eq!(level.sectors.len(), 4);
+        assert!(level.walls.arr.iter().all(|w| w.a != w.b));
+
+        // the steps keep their 16 unit depth, and rise by less than
+        // STEP_HEIGHT each
+        let step = &level.walls.arr[level.sectors.arr[2].firstwall];
+        assert_eq!(
+            (step.a.clone(), step.b.clone()),
+            (V2i::new(64, 0), V2i::new(64, 64))
+        );
+        let scale = EYE_Z / DOOM_EYE_Z;
+        let floors: Vec<_> = level.sectors.arr[1..].iter().map(|s| s.zfloor).collect();
+        assert_eq!(floors, vec![0.0, 16.0 * scale, 32.0 * scale]);
+        assert!(16.0 * scale < STEP_HEIGHT);
+    }
+
+    #[test]
+    fn crossed_sector() {
+        // a bow tie, its linedefs crossing in the middle
+        let mut lumps = two_rooms();
+        lumps[2].1 = linedefs(&[
+            (0, 2, 0, NO_SIDEDEF),
+            (2, 3, 0, NO_SIDEDEF),
+            (3, 1, 0, NO_SIDEDEF),
+            (1, 0, 0, NO_SIDEDEF),
+        ]);
+        lumps[5].1 = sectors(&[(0, 128, "CEIL3_5")]);
+
+        match import(&lumps) {
+            Err(LevelError::Unsupported { message, .. }) => assert_eq!(
+                message,
+                "map MAP01 sector 0 can't be split into convex pieces, its linedefs cross"
+            ),
+            other => panic!("expected an unsupported map, got {:?}", other),
+        }
+    }
+}
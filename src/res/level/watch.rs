@@ -0,0 +1,95 @@
+use std::fs;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Polls a level file's modification time so it can be reloaded while the
+/// engine is running.
+#[derive(Debug, Clone)]
+pub struct LevelWatcher {
+    pub path: String,
+    interval: Duration,
+    last_poll: Instant,
+    modified: Option<SystemTime>,
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+impl LevelWatcher {
+    /// Watches `path`, looking at it at most once per `interval`.
+    pub fn new(path: &str, interval: Duration) -> Self {
+        LevelWatcher {
+            path: path.to_string(),
+            interval,
+            last_poll: Instant::now(),
+            modified: modified(path),
+        }
+    }
+
+    /// True once for every change to the file's modification time. A file
+    /// that is missing (say, mid-save) counts as unchanged.
+    pub fn changed(&mut self) -> bool {
+        if self.last_poll.elapsed() < self.interval {
+            return false;
+        }
+        self.last_poll = Instant::now();
+
+        match modified(&self.path) {
+            Some(time) if Some(time) != self.modified => {
+                self.modified = Some(time);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::res::testing::TempFile;
+
+    /// Rewrites the file at `path`, moving its modification time on by
+    /// `secs` from `base` so the change shows however coarse the
+    /// filesystem's clock is.
+    fn rewrite(path: &str, base: SystemTime, secs: u64) {
+        fs::write(path, format!("# rewrite {}\n", secs)).unwrap();
+        let file = fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(base + Duration::from_secs(secs)).unwrap();
+    }
+
+    #[test]
+    fn changes_fire_once_per_interval() {
+        let file = TempFile::new("watched.txt");
+        fs::write(file.path(), "# level\n").unwrap();
+        let base = modified(file.path()).unwrap();
+
+        let interval = Duration::from_secs(60);
+        let mut watcher = LevelWatcher::new(file.path(), interval);
+        // as if the interval had already gone by
+        watcher.last_poll -= interval;
+        assert!(!watcher.changed(), "nothing has changed yet");
+
+        watcher.last_poll -= interval;
+        rewrite(file.path(), base, 1);
+        assert!(watcher.changed());
+        assert!(!watcher.changed(), "the same change fired twice");
+
+        // a second save straight after waits for the interval
+        rewrite(file.path(), base, 2);
+        assert!(!watcher.changed());
+
+        watcher.last_poll -= interval;
+        assert!(watcher.changed());
+        assert!(!watcher.changed());
+    }
+
+    #[test]
+    fn missing_files_are_unchanged() {
+        let file = TempFile::new("watched-missing.txt");
+        let interval = Duration::from_secs(60);
+        let mut watcher = LevelWatcher::new(file.path(), interval);
+        watcher.last_poll -= interval;
+        assert!(!watcher.changed());
+    }
+}
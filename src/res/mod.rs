@@ -1,2 +1,11 @@
-pub mod util;
+pub mod framebuffer;
 pub mod game;
+#[cfg(test)]
+mod golden;
+pub mod level;
+pub mod present;
+pub mod screenshot;
+#[cfg(test)]
+mod testing;
+pub mod texture;
+pub mod util;
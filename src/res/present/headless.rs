@@ -0,0 +1,26 @@
+use super::Presenter;
+use crate::res::framebuffer::Framebuffer;
+
+/// Presents to nothing, for running without a window or display. Keeps the
+/// last frame so it can be inspected or saved.
+#[derive(Debug, Clone, Default)]
+pub struct HeadlessPresenter {
+    pub frames: usize,
+    pub last: Option<Framebuffer>,
+}
+
+impl HeadlessPresenter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Presenter for HeadlessPresenter {
+    fn present(&mut self, frame: &Framebuffer) {
+        self.frames += 1;
+        match &mut self.last {
+            Some(last) => last.clone_from(frame),
+            _ => self.last = Some(frame.clone()),
+        }
+    }
+}
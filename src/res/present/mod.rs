@@ -0,0 +1,10 @@
+pub mod headless;
+pub mod sdl;
+
+use super::framebuffer::Framebuffer;
+
+/// Shows finished frames. The renderer only ever draws into a `Framebuffer`,
+/// so the same frame can go to a window or nowhere at all.
+pub trait Presenter {
+    fn present(&mut self, frame: &Framebuffer);
+}
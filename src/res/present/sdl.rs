@@ -0,0 +1,176 @@
+use std::ffi::{CStr, CString};
+use std::slice;
+
+use super::Presenter;
+use crate::res::framebuffer::Framebuffer;
+
+use sdl2_sys::*;
+
+fn sdl_error() -> String {
+    unsafe { CStr::from_ptr(SDL_GetError()) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Presents frames in an SDL window, scaled up to the window size.
+#[derive(Debug)]
+pub struct SdlPresenter {
+    pub window: *mut SDL_Window,
+    pub renderer: *mut SDL_Renderer,
+    pub texture: *mut SDL_Texture,
+    pub debug: *mut SDL_Texture,
+    width: usize,
+    height: usize,
+}
+
+impl SdlPresenter {
+    /// Brings up SDL and a `window_width` x `window_height` window for
+    /// frames of `width` x `height` pixels.
+    pub fn new(
+        title: &str,
+        window_width: i32,
+        window_height: i32,
+        width: usize,
+        height: usize,
+    ) -> Result<Self, String> {
+        unsafe {
+            if SDL_Init(SDL_INIT_VIDEO) != 0 {
+                return Err(format!("SDL failed to initialize: {}", sdl_error()));
+            }
+
+            let title = CString::new(title).map_err(|e| e.to_string())?;
+            let window = SDL_CreateWindow(
+                title.as_ptr(),
+                SDL_WINDOWPOS_CENTERED_MASK as i32,
+                SDL_WINDOWPOS_CENTERED_MASK as i32,
+                window_width,
+                window_height,
+                SDL_WindowFlags::SDL_WINDOW_ALLOW_HIGHDPI as u32,
+            );
+            if window.is_null() {
+                let error = format!("failed to create window: {}", sdl_error());
+                SDL_Quit();
+                return Err(error);
+            }
+
+            // from here on drop cleans up whatever was created
+            let mut presenter = SdlPresenter {
+                window,
+                renderer: std::ptr::null_mut(),
+                texture: std::ptr::null_mut(),
+                debug: std::ptr::null_mut(),
+                width,
+                height,
+            };
+
+            presenter.renderer = SDL_CreateRenderer(
+                window,
+                -1,
+                SDL_RendererFlags::SDL_RENDERER_PRESENTVSYNC as u32,
+            );
+            if presenter.renderer.is_null() {
+                return Err(format!("failed to create renderer: {}", sdl_error()));
+            }
+
+            presenter.texture = SDL_CreateTexture(
+                presenter.renderer,
+                SDL_PixelFormatEnum::SDL_PIXELFORMAT_ABGR8888 as u32,
+                SDL_TextureAccess::SDL_TEXTUREACCESS_STREAMING as i32,
+                width as i32,
+                height as i32,
+            );
+            presenter.debug = SDL_CreateTexture(
+                presenter.renderer,
+                SDL_PixelFormatEnum::SDL_PIXELFORMAT_ABGR8888 as u32,
+                SDL_TextureAccess::SDL_TEXTUREACCESS_TARGET as i32,
+                128,
+                128,
+            );
+            if presenter.texture.is_null() || presenter.debug.is_null() {
+                return Err(format!("failed to create textures: {}", sdl_error()));
+            }
+
+            Ok(presenter)
+        }
+    }
+}
+
+impl Presenter for SdlPresenter {
+    fn present(&mut self, frame: &Framebuffer) {
+        assert!(
+            frame.width == self.width && frame.height == self.height,
+            "frame is {}x{}, the window texture is {}x{}",
+            frame.width,
+            frame.height,
+            self.width,
+            self.height
+        );
+
+        let mut px: *mut std::ffi::c_void = std::ptr::null_mut();
+        let mut pitch: i32 = 0;
+
+        unsafe {
+            // without the pixels there is nothing to draw, so the frame is
+            // dropped and the window keeps showing the last one
+            if SDL_LockTexture(self.texture, std::ptr::null(), &mut px, &mut pitch) != 0 {
+                eprintln!("error: failed to lock texture: {}", sdl_error());
+                return;
+            }
+            let px_slice = slice::from_raw_parts_mut(px as *mut u8, self.height * pitch as usize);
+            for (y, row) in frame.pixels.chunks_exact(frame.width).enumerate() {
+                let dst = &mut px_slice[y * pitch as usize..][..frame.width * 4];
+                for (d, p) in dst.chunks_exact_mut(4).zip(row) {
+                    d.copy_from_slice(&p.to_ne_bytes());
+                }
+            }
+            SDL_UnlockTexture(self.texture);
+
+            SDL_SetRenderTarget(self.renderer, std::ptr::null_mut());
+            SDL_SetRenderDrawColor(self.renderer, 0, 0, 0, 0xFF);
+            SDL_SetRenderDrawBlendMode(self.renderer, SDL_BlendMode::SDL_BLENDMODE_NONE);
+
+            SDL_RenderClear(self.renderer);
+            SDL_RenderCopyEx(
+                self.renderer,
+                self.texture,
+                std::ptr::null(),
+                std::ptr::null(),
+                0.0,
+                std::ptr::null(),
+                SDL_RendererFlip::SDL_FLIP_VERTICAL,
+            );
+
+            SDL_SetTextureBlendMode(self.debug, SDL_BlendMode::SDL_BLENDMODE_BLEND);
+            SDL_RenderCopy(
+                self.renderer,
+                self.debug,
+                std::ptr::null(),
+                &SDL_Rect {
+                    x: 0,
+                    y: 0,
+                    w: 512,
+                    h: 512,
+                },
+            );
+            SDL_RenderPresent(self.renderer);
+        }
+    }
+}
+
+impl Drop for SdlPresenter {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.debug.is_null() {
+                SDL_DestroyTexture(self.debug);
+            }
+            if !self.texture.is_null() {
+                SDL_DestroyTexture(self.texture);
+            }
+            if !self.renderer.is_null() {
+                SDL_DestroyRenderer(self.renderer);
+            }
+            SDL_DestroyWindow(self.window);
+            SDL_Quit();
+        }
+    }
+}
@@ -0,0 +1,201 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use super::framebuffer::Framebuffer;
+
+/// Largest block a stored (uncompressed) deflate block can hold.
+const STORED_BLOCK_MAX: usize = 0xFFFF;
+
+/// The red, green and blue bytes of a framebuffer pixel.
+pub fn rgb(px: u32) -> [u8; 3] {
+    [px as u8, (px >> 8) as u8, (px >> 16) as u8]
+}
+
+/// Saves `frame` as PNG if `path` ends in `.png`, otherwise as PPM.
+pub fn save_screenshot<P: AsRef<Path>>(path: P, frame: &Framebuffer) -> io::Result<()> {
+    let path = path.as_ref();
+    let png = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("png"));
+
+    let mut out = BufWriter::new(File::create(path)?);
+    if png {
+        write_png(&mut out, frame)?;
+    } else {
+        write_ppm(&mut out, frame)?;
+    }
+    out.flush()
+}
+
+/// Writes `frame` as a binary (P6) PPM, top row first.
+pub fn write_ppm<W: Write>(out: &mut W, frame: &Framebuffer) -> io::Result<()> {
+    write!(out, "P6\n{} {}\n255\n", frame.width, frame.height)?;
+    for row in frame.rows_top_down() {
+        for &px in row {
+            out.write_all(&rgb(px))?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes `frame` as an 8-bit RGB PNG. The image data is stored without
+/// compression, which keeps the writer tiny at the cost of file size.
+pub fn write_png<W: Write>(out: &mut W, frame: &Framebuffer) -> io::Result<()> {
+    let too_big = || io::Error::new(io::ErrorKind::InvalidInput, "frame too large for PNG");
+    let width = u32::try_from(frame.width).map_err(|_| too_big())?;
+    let height = u32::try_from(frame.height).map_err(|_| too_big())?;
+
+    // every scanline starts with filter type 0, none
+    let mut raw = Vec::with_capacity(frame.height * (1 + frame.width * 3));
+    for row in frame.rows_top_down() {
+        raw.push(0);
+        for &px in row {
+            raw.extend_from_slice(&rgb(px));
+        }
+    }
+
+    out.write_all(b"\x89PNG\r\n\x1a\n")?;
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&width.to_be_bytes());
+    ihdr.extend_from_slice(&height.to_be_bytes());
+    // 8 bits per channel, truecolour, deflate, adaptive filtering, no interlace
+    ihdr.extend_from_slice(&[8, 2, 0, 0, 0]);
+    write_chunk(out, b"IHDR", &ihdr)?;
+
+    write_chunk(out, b"IDAT", &zlib_stored(&raw))?;
+    write_chunk(out, b"IEND", &[])
+}
+
+fn write_chunk<W: Write>(out: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    let len = u32::try_from(data.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "PNG chunk too large"))?;
+
+    out.write_all(&len.to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+
+    let crc = crc32_update(crc32_update(!0, kind), data);
+    out.write_all(&(!crc).to_be_bytes())
+}
+
+/// Wraps `data` in a zlib stream made of stored deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let blocks = usize::max(data.len().div_ceil(STORED_BLOCK_MAX), 1);
+    let mut out = Vec::with_capacity(data.len() + blocks * 5 + 6);
+
+    // deflate, 32K window, no preset dictionary, check bits make it % 31
+    out.extend_from_slice(&[0x78, 0x01]);
+
+    let mut chunks = data.chunks(STORED_BLOCK_MAX).peekable();
+    if chunks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(chunk) = chunks.next() {
+        let last = chunks.peek().is_none();
+        let len = chunk.len() as u16;
+
+        out.push(last as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(chunk);
+    }
+
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn crc32_update(mut crc: u32, data: &[u8]) -> u32 {
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                0xEDB88320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+
+    let (mut a, mut b) = (1u32, 0u32);
+    // 5552 bytes is the most that can be summed before b overflows
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::res::texture::png::decode_png;
+
+    /// A frame where every pixel is different, with junk in the unused top
+    /// byte that the writers must drop.
+    fn frame(width: usize, height: usize) -> Framebuffer {
+        let mut frame = Framebuffer::new(width, height);
+        for (i, px) in frame.pixels.iter_mut().enumerate() {
+            *px = 0xAB00_0000 | (i as u32).wrapping_mul(0x0001_0307) & 0x00FF_FFFF;
+        }
+        frame
+    }
+
+    /// What decoding a screenshot of `frame` should give: opaque pixels,
+    /// top row first.
+    fn expected(frame: &Framebuffer) -> Vec<u32> {
+        frame
+            .rows_top_down()
+            .flatten()
+            .map(|&px| px | 0xFF00_0000)
+            .collect()
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let frame = frame(5, 3);
+        let mut png = vec![];
+        write_png(&mut png, &frame).unwrap();
+
+        let image = decode_png(&png).unwrap();
+        assert_eq!((image.width, image.height), (5, 3));
+        assert_eq!(image.pixels, expected(&frame));
+
+        // the CRC of an empty IEND chunk is always the same
+        assert!(png.ends_with(&[0xAE, 0x42, 0x60, 0x82]));
+    }
+
+    #[test]
+    fn png_round_trip_over_several_blocks() {
+        // 200 * 3 + 1 bytes a row makes 72120 bytes, two stored blocks
+        let frame = frame(200, 120);
+        let mut png = vec![];
+        write_png(&mut png, &frame).unwrap();
+
+        let image = decode_png(&png).unwrap();
+        assert_eq!((image.width, image.height), (200, 120));
+        assert_eq!(image.pixels, expected(&frame));
+    }
+
+    #[test]
+    fn ppm_is_top_row_first() {
+        let frame = frame(2, 2);
+        let mut ppm = vec![];
+        write_ppm(&mut ppm, &frame).unwrap();
+
+        let header = b"P6\n2 2\n255\n";
+        assert!(ppm.starts_with(header));
+        let body: Vec<u8> = expected(&frame).iter().flat_map(|&px| rgb(px)).collect();
+        assert_eq!(&ppm[header.len()..], &body[..]);
+    }
+}
@@ -0,0 +1,36 @@
+//! Helpers shared by the unit tests.
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The repository root, where `level.txt` and `tests/` are.
+pub fn root() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+}
+
+/// A path in the temp directory, ending in `name` and unique to this
+/// `TempFile`, whose file, or directory and everything in it, is removed
+/// again when this is dropped.
+pub struct TempFile(PathBuf);
+
+/// Counts `TempFile`s, so tests running at once never share one.
+static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+impl TempFile {
+    pub fn new(name: &str) -> Self {
+        let n = NEXT.fetch_add(1, Ordering::Relaxed);
+        let file = format!("zengine-{}-{}-{}", std::process::id(), n, name);
+        TempFile(std::env::temp_dir().join(file))
+    }
+
+    pub fn path(&self) -> &str {
+        self.0.to_str().unwrap()
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,180 @@
+use super::{abgr, image_bytes, Image};
+
+const BI_RGB: u32 = 0;
+const BI_BITFIELDS: u32 = 3;
+
+fn le16(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn le32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+/// Decodes an uncompressed 8-bit paletted, 24-bit or 32-bit BMP.
+pub fn decode_bmp(bytes: &[u8]) -> Result<Image, String> {
+    if bytes.len() < 54 || &bytes[..2] != b"BM" {
+        return Err("not a BMP file".to_string());
+    }
+
+    let data_at = le32(bytes, 10) as usize;
+    let header_len = le32(bytes, 14) as usize;
+    if header_len < 40 {
+        return Err("old-style BMP headers are not supported".to_string());
+    }
+
+    let width = le32(bytes, 18) as i32;
+    let height = le32(bytes, 22) as i32;
+    let bpp = le16(bytes, 28);
+    let compression = le32(bytes, 30);
+    let ncolors = le32(bytes, 46) as usize;
+
+    if width <= 0 || height == 0 {
+        return Err("BMP has no pixels".to_string());
+    }
+    let ok = match bpp {
+        8 | 24 => compression == BI_RGB,
+        32 => compression == BI_RGB || compression == BI_BITFIELDS,
+        _ => false,
+    };
+    if !ok {
+        return Err(format!(
+            "{}-bit BMPs with compression {} are not supported",
+            bpp, compression
+        ));
+    }
+
+    // a negative height means the rows are stored top-down
+    let top_down = height < 0;
+    let (width, height) = (width as usize, height.unsigned_abs() as usize);
+    image_bytes(width, height, bpp as usize / 8, 0)?;
+
+    let palette: Vec<u32> = if bpp == 8 {
+        let at = 14 + header_len;
+        let n = match ncolors {
+            0 => 256,
+            1..=256 => ncolors,
+            _ => return Err(format!("palette of {} colours is too large", ncolors)),
+        };
+        bytes
+            .get(at..at + n * 4)
+            .ok_or("palette runs past the end of the file")?
+            .chunks_exact(4)
+            .map(|c| abgr(c[2], c[1], c[0], 0xFF))
+            .collect()
+    } else {
+        vec![]
+    };
+
+    // rows are padded to 4 bytes
+    let stride = (width * bpp as usize / 8).div_ceil(4) * 4;
+    let data = data_at
+        .checked_add(stride * height)
+        .and_then(|end| bytes.get(data_at..end))
+        .ok_or("pixel data runs past the end of the file")?;
+
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let row = if top_down { y } else { height - 1 - y };
+        let row = &data[row * stride..];
+
+        for x in 0..width {
+            pixels.push(match bpp {
+                8 => palette.get(row[x] as usize).copied().unwrap_or(0xFF000000),
+                24 => abgr(row[x * 3 + 2], row[x * 3 + 1], row[x * 3], 0xFF),
+                // the alpha byte is unused by most writers
+                _ => abgr(row[x * 4 + 2], row[x * 4 + 1], row[x * 4], 0xFF),
+            });
+        }
+    }
+
+    Ok(Image {
+        width,
+        height,
+        pixels,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A BMP with a 40 byte info header, `palette` entries and `data`.
+    fn bmp(width: i32, height: i32, bpp: u16, palette: &[[u8; 4]], data: &[u8]) -> Vec<u8> {
+        let data_at = 54 + palette.len() as u32 * 4;
+        let header = [
+            &b"BM"[..],
+            &(data_at + data.len() as u32).to_le_bytes(),
+            &[0; 4],
+            &data_at.to_le_bytes(),
+            &40u32.to_le_bytes(),
+            &width.to_le_bytes(),
+            &height.to_le_bytes(),
+            &1u16.to_le_bytes(),
+            &bpp.to_le_bytes(),
+            &BI_RGB.to_le_bytes(),
+            &[0; 12],
+            &(palette.len() as u32).to_le_bytes(),
+            &[0; 4],
+        ]
+        .concat();
+        [header, palette.concat(), data.to_vec()].concat()
+    }
+
+    #[test]
+    fn bottom_up_24_bit() {
+        // BGR, rows padded to 4 bytes, the bottom row first
+        let data = [0, 0, 255, 0, 255, 0, 0, 0, 255, 0, 0, 0, 0, 0, 0, 0];
+        let image = decode_bmp(&bmp(2, 2, 24, &[], &data)).unwrap();
+        assert_eq!(
+            image.pixels,
+            vec![
+                abgr(0, 0, 255, 0xFF),
+                abgr(0, 0, 0, 0xFF),
+                abgr(255, 0, 0, 0xFF),
+                abgr(0, 255, 0, 0xFF),
+            ]
+        );
+    }
+
+    #[test]
+    fn top_down_paletted() {
+        let palette = [[0, 0, 0, 0], [255, 128, 0, 0]];
+        let image = decode_bmp(&bmp(3, -1, 8, &palette, &[1, 0, 9, 0])).unwrap();
+        assert_eq!(
+            image.pixels,
+            vec![abgr(0, 128, 255, 0xFF), abgr(0, 0, 0, 0xFF), 0xFF000000]
+        );
+    }
+
+    #[test]
+    fn bad_files() {
+        let err = |bytes: &[u8]| decode_bmp(bytes).unwrap_err();
+
+        assert_eq!(err(b"BM"), "not a BMP file");
+        assert_eq!(err(&bmp(0, 1, 24, &[], &[])), "BMP has no pixels");
+        assert_eq!(
+            err(&bmp(1, 1, 16, &[], &[0; 4])),
+            "16-bit BMPs with compression 0 are not supported"
+        );
+        assert_eq!(
+            err(&bmp(2, 2, 24, &[], &[0; 8])),
+            "pixel data runs past the end of the file"
+        );
+        assert_eq!(
+            err(&bmp(i32::MAX, i32::MIN, 32, &[], &[])),
+            "2147483647x2147483648 image is larger than 16384x16384"
+        );
+
+        let mut huge_palette = bmp(1, 1, 8, &[], &[0; 4]);
+        huge_palette[46..50].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(
+            err(&huge_palette),
+            "palette of 4294967295 colours is too large"
+        );
+
+        let mut far_data = bmp(1, 1, 24, &[], &[0; 4]);
+        far_data[10..14].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(err(&far_data), "pixel data runs past the end of the file");
+    }
+}
@@ -0,0 +1,302 @@
+//! Decompression of zlib streams (RFC 1950/1951), for reading PNGs.
+
+/// Bits are read least significant first, as deflate packs them.
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit: u32,
+}
+
+impl<'a> Bits<'a> {
+    fn bit(&mut self) -> Result<u32, String> {
+        let byte = *self
+            .data
+            .get(self.pos)
+            .ok_or("compressed data ends early")?;
+        let b = (byte as u32 >> self.bit) & 1;
+        self.bit += 1;
+        if self.bit == 8 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+        Ok(b)
+    }
+
+    fn bits(&mut self, n: u32) -> Result<u32, String> {
+        let mut value = 0;
+        for i in 0..n {
+            value |= self.bit()? << i;
+        }
+        Ok(value)
+    }
+
+    fn align(&mut self) {
+        if self.bit != 0 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+    }
+}
+
+/// Canonical Huffman code, decoded by counting codes of each length.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut offsets = [0u16; 16];
+        for len in 1..16 {
+            offsets[len] = offsets[len - 1] + counts[len - 1];
+        }
+
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Result<u16, String> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            code |= bits.bit()? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("bad Huffman code".to_string())
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// Order the code length code lengths are stored in.
+const CLEN_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// Decompresses a zlib stream.
+pub fn inflate_zlib(data: &[u8]) -> Result<Vec<u8>, String> {
+    let check = data.len() >= 2 && (data[0] as u16 * 256 + data[1] as u16).is_multiple_of(31);
+    if !check || data[0] & 0x0F != 8 {
+        return Err("bad zlib header".to_string());
+    }
+    if data[1] & 0x20 != 0 {
+        return Err("zlib preset dictionaries are not supported".to_string());
+    }
+
+    inflate(&data[2..])
+}
+
+/// Decompresses raw deflate data.
+pub fn inflate(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut bits = Bits {
+        data,
+        pos: 0,
+        bit: 0,
+    };
+    let mut out = Vec::new();
+
+    loop {
+        let last = bits.bit()?;
+        match bits.bits(2)? {
+            0 => {
+                bits.align();
+                let header = data
+                    .get(bits.pos..bits.pos + 4)
+                    .ok_or("compressed data ends early")?;
+                let len = u16::from_le_bytes([header[0], header[1]]);
+                let nlen = u16::from_le_bytes([header[2], header[3]]);
+                if len != !nlen {
+                    return Err("stored block length is corrupt".to_string());
+                }
+                let start = bits.pos + 4;
+                let block = data
+                    .get(start..start + len as usize)
+                    .ok_or("compressed data ends early")?;
+                out.extend_from_slice(block);
+                bits.pos = start + len as usize;
+            }
+            1 => {
+                let mut lengths = [0u8; 288];
+                lengths[..144].fill(8);
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                lengths[280..].fill(8);
+                let lit = Huffman::new(&lengths);
+                let dist = Huffman::new(&[5; 30]);
+                inflate_block(&mut bits, &lit, &dist, &mut out)?;
+            }
+            2 => {
+                let (lit, dist) = dynamic_codes(&mut bits)?;
+                inflate_block(&mut bits, &lit, &dist, &mut out)?;
+            }
+            _ => return Err("bad deflate block type".to_string()),
+        }
+
+        if last == 1 {
+            return Ok(out);
+        }
+    }
+}
+
+fn dynamic_codes(bits: &mut Bits) -> Result<(Huffman, Huffman), String> {
+    let nlit = bits.bits(5)? as usize + 257;
+    let ndist = bits.bits(5)? as usize + 1;
+    let nclen = bits.bits(4)? as usize + 4;
+
+    let mut clen = [0u8; 19];
+    for &i in &CLEN_ORDER[..nclen] {
+        clen[i] = bits.bits(3)? as u8;
+    }
+    let clen = Huffman::new(&clen);
+
+    let mut lengths = Vec::with_capacity(nlit + ndist);
+    while lengths.len() < nlit + ndist {
+        let (value, repeat) = match clen.decode(bits)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => {
+                let &previous = lengths
+                    .last()
+                    .ok_or("length repeat with nothing before it")?;
+                (previous, 3 + bits.bits(2)?)
+            }
+            17 => (0, 3 + bits.bits(3)?),
+            _ => (0, 11 + bits.bits(7)?),
+        };
+        lengths.extend(std::iter::repeat_n(value, repeat as usize));
+    }
+    if lengths.len() != nlit + ndist {
+        return Err("code lengths overrun".to_string());
+    }
+
+    Ok((
+        Huffman::new(&lengths[..nlit]),
+        Huffman::new(&lengths[nlit..]),
+    ))
+}
+
+fn inflate_block(
+    bits: &mut Bits,
+    lit: &Huffman,
+    dist: &Huffman,
+    out: &mut Vec<u8>,
+) -> Result<(), String> {
+    loop {
+        let symbol = lit.decode(bits)? as usize;
+        match symbol {
+            0..=255 => out.push(symbol as u8),
+            256 => return Ok(()),
+            257..=285 => {
+                let i = symbol - 257;
+                let len = LENGTH_BASE[i] as usize + bits.bits(LENGTH_EXTRA[i] as u32)? as usize;
+
+                let d = dist.decode(bits)? as usize;
+                if d >= 30 {
+                    return Err("bad distance code".to_string());
+                }
+                let distance = DIST_BASE[d] as usize + bits.bits(DIST_EXTRA[d] as u32)? as usize;
+                if distance > out.len() {
+                    return Err("distance reaches before the start of the data".to_string());
+                }
+
+                // byte by byte, the copy may overlap what it is writing
+                let start = out.len() - distance;
+                for i in 0..len {
+                    out.push(out[start + i]);
+                }
+            }
+            _ => return Err("bad length code".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stored_block() {
+        let data = [0x78, 0x01, 1, 3, 0, !3, 0xFF, b'a', b'b', b'c'];
+        assert_eq!(inflate_zlib(&data).unwrap(), b"abc");
+    }
+
+    #[test]
+    fn fixed_codes() {
+        // "hello " repeated, mostly as back references
+        let data = [
+            0x78, 0x01, 0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0xc8, 0x40, 0x27, 0x15, 0x01, 0x70,
+            0xd5, 0x08, 0xd2,
+        ];
+        assert_eq!(inflate_zlib(&data).unwrap(), b"hello hello hello hello!");
+    }
+
+    #[test]
+    fn dynamic_codes() {
+        let data = [
+            0x78, 0xda, 0x05, 0xc1, 0x01, 0x01, 0x00, 0x00, 0x08, 0x02, 0xa0, 0xad, 0xa8, 0xfd,
+            0xbf, 0x10, 0x98, 0x24, 0x42, 0xaf, 0xc5, 0xd9, 0x03, 0x50, 0x81, 0x07, 0xad,
+        ];
+        assert_eq!(inflate_zlib(&data).unwrap(), b"adabbbabaaceccaaaead");
+    }
+
+    #[test]
+    fn bad_streams() {
+        assert_eq!(inflate_zlib(&[0x78]).unwrap_err(), "bad zlib header");
+        assert_eq!(
+            inflate_zlib(&[0x78, 0x01, 1, 3, 0, 0, 0]).unwrap_err(),
+            "stored block length is corrupt"
+        );
+        assert_eq!(
+            inflate_zlib(&[0x78, 0x01, 1, 3, 0, !3, 0xFF, b'a']).unwrap_err(),
+            "compressed data ends early"
+        );
+        assert_eq!(inflate(&[0x07]).unwrap_err(), "bad deflate block type");
+    }
+
+    #[test]
+    fn distance_before_start() {
+        // a fixed block starting with a length 3, distance 1 copy
+        let mut bits = vec![1, 1, 0];
+        bits.extend([0, 0, 0, 0, 0, 0, 1]);
+        bits.extend([0, 0, 0, 0, 0]);
+        let bytes: Vec<u8> = bits
+            .chunks(8)
+            .map(|byte| byte.iter().rev().fold(0, |acc, &bit| acc << 1 | bit))
+            .collect();
+        assert_eq!(
+            inflate(&bytes).unwrap_err(),
+            "distance reaches before the start of the data"
+        );
+    }
+}
@@ -0,0 +1,564 @@
+pub mod bmp;
+pub mod inflate;
+pub mod png;
+pub mod ppm;
+
+use std::collections::HashMap;
+use std::fmt;
+
+use super::level::Level;
+use super::util::constants::{SKY_TEXTURE, TAU, TEXTURE_NON};
+use super::util::math::clamp;
+
+/// File extensions `load_dir` picks up.
+pub const EXTENSIONS: [&str; 3] = ["png", "bmp", "ppm"];
+
+/// Widest or tallest image the decoders accept, so a corrupt header can't
+/// ask for gigabytes of pixels.
+pub const MAX_SIZE: usize = 16384;
+
+/// Bytes needed for `height` rows of `width` pixels of `bpp` bytes each, plus
+/// `row_extra` bytes per row, or an error if the image is too large.
+fn image_bytes(width: usize, height: usize, bpp: usize, row_extra: usize) -> Result<usize, String> {
+    if width > MAX_SIZE || height > MAX_SIZE {
+        return Err(format!(
+            "{}x{} image is larger than {}x{}",
+            width, height, MAX_SIZE, MAX_SIZE
+        ));
+    }
+    width
+        .checked_mul(bpp)
+        .and_then(|stride| stride.checked_add(row_extra))
+        .and_then(|stride| stride.checked_mul(height))
+        .ok_or_else(|| "image is too large".to_string())
+}
+
+/// Packs 8-bit channels into the ABGR8888 layout the framebuffer uses.
+pub fn abgr(r: u8, g: u8, b: u8, a: u8) -> u32 {
+    (a as u32) << 24 | (b as u32) << 16 | (g as u32) << 8 | r as u32
+}
+
+/// An ABGR8888 image, row 0 at the top.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u32>,
+}
+
+/// An image along with its mip levels, each half the size of the one before
+/// down to 1x1. One copy of the texture covers one world unit in each
+/// direction.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Texture {
+    /// `mips[0]` is the full size image
+    pub mips: Vec<Image>,
+}
+
+/// Textures by id, and ids by name. Id 0 means "no texture", surfaces using
+/// it keep their flat colour. A name keeps its id when it is loaded again,
+/// so ids stay valid while textures are reloaded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Textures {
+    pub arr: Vec<Texture>,
+    names: HashMap<String, usize>,
+    /// the name of each id, "" for the placeholder
+    ids: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum TextureError {
+    Io { path: String, error: std::io::Error },
+    Corrupt { path: String, message: String },
+}
+
+impl fmt::Display for TextureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TextureError::Io { path, error } => write!(f, "error: {}: {}", path, error),
+            TextureError::Corrupt { path, message } => write!(f, "error: {}: {}", path, message),
+        }
+    }
+}
+
+impl std::error::Error for TextureError {}
+
+impl Image {
+    pub fn new(width: usize, height: usize, pixels: Vec<u32>) -> Self {
+        assert_eq!(pixels.len(), width * height, "image size mismatch");
+        Image {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Builds an image from a function of the pixel position.
+    pub fn generate(width: usize, height: usize, f: impl Fn(usize, usize) -> u32) -> Self {
+        let pixels = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| f(x, y))
+            .collect();
+        Self::new(width, height, pixels)
+    }
+
+    /// Column of the texel at horizontal texture coordinate `u`, wrapping.
+    pub fn column(&self, u: f32) -> usize {
+        usize::min(
+            (u.rem_euclid(1.0) * self.width as f32) as usize,
+            self.width - 1,
+        )
+    }
+
+    /// Texel at `column` and vertical texture coordinate `v`, wrapping.
+    pub fn texel(&self, column: usize, v: f32) -> u32 {
+        let row = usize::min(
+            (v.rem_euclid(1.0) * self.height as f32) as usize,
+            self.height - 1,
+        );
+        self.pixels[row * self.width + column]
+    }
+
+    /// Half the size in each direction, every pixel the average of the
+    /// (up to) 2x2 pixels it covers.
+    fn downsample(&self) -> Image {
+        let (width, height) = (
+            usize::max(self.width / 2, 1),
+            usize::max(self.height / 2, 1),
+        );
+
+        Image::generate(width, height, |x, y| {
+            let xs = [x * 2, usize::min(x * 2 + 1, self.width - 1)];
+            let ys = [y * 2, usize::min(y * 2 + 1, self.height - 1)];
+
+            let mut sum = [0u32; 4];
+            for &sy in &ys {
+                for &sx in &xs {
+                    let px = self.pixels[sy * self.width + sx];
+                    for (c, s) in sum.iter_mut().enumerate() {
+                        *s += (px >> (c * 8)) & 0xFF;
+                    }
+                }
+            }
+
+            sum.iter()
+                .enumerate()
+                .fold(0, |px, (c, s)| px | ((s + 2) / 4) << (c * 8))
+        })
+    }
+}
+
+impl Texture {
+    pub fn new(image: Image) -> Self {
+        assert!(image.width > 0 && image.height > 0, "empty texture");
+
+        let mut mips = vec![image];
+        while let Some(last) = mips.last().filter(|m| m.width > 1 || m.height > 1) {
+            let next = last.downsample();
+            mips.push(next);
+        }
+        Texture { mips }
+    }
+
+    pub fn width(&self) -> usize {
+        self.mips[0].width
+    }
+
+    pub fn height(&self) -> usize {
+        self.mips[0].height
+    }
+
+    /// The mip level to draw with when one screen pixel covers
+    /// `texels_per_pixel` texels of the full size image.
+    pub fn mip(&self, texels_per_pixel: f32) -> &Image {
+        let level = if texels_per_pixel > 1.0 {
+            texels_per_pixel.log2() as usize
+        } else {
+            0
+        };
+        &self.mips[usize::min(level, self.mips.len() - 1)]
+    }
+}
+
+/// Reads a PNG, BMP or PPM image, going by its contents rather than its
+/// extension.
+pub fn load_image(path: &str) -> Result<Image, TextureError> {
+    let bytes = std::fs::read(path).map_err(|error| TextureError::Io {
+        path: path.to_string(),
+        error,
+    })?;
+
+    let image = if bytes.starts_with(b"\x89PNG") {
+        png::decode_png(&bytes)
+    } else if bytes.starts_with(b"BM") {
+        bmp::decode_bmp(&bytes)
+    } else if bytes.starts_with(b"P6") || bytes.starts_with(b"P3") {
+        ppm::decode_ppm(&bytes)
+    } else {
+        Err("not a PNG, BMP or PPM image".to_string())
+    };
+
+    image
+        .and_then(|image| match image.width * image.height {
+            0 => Err("image has no pixels".to_string()),
+            _ => Ok(image),
+        })
+        .map_err(|message| TextureError::Corrupt {
+            path: path.to_string(),
+            message,
+        })
+}
+
+impl Textures {
+    /// Starts out holding only the placeholder for texture 0.
+    pub fn new() -> Self {
+        Textures {
+            arr: vec![Texture::default()],
+            names: HashMap::new(),
+            ids: vec![String::new()],
+        }
+    }
+
+    /// A few generated textures, so levels can be textured without any image
+    /// files: "bricks" (id 1), "checker" (2), "planks" (3), the "sky"
+    /// panorama (4) and an "orb" sprite (5).
+    pub fn builtin() -> Self {
+        let mut textures = Self::new();
+
+        textures.add(
+            "bricks",
+            Image::generate(64, 64, |x, y| {
+                let row = y / 16;
+                let x = (x + row % 2 * 16) % 64;
+                if y % 16 == 15 || x % 32 == 31 {
+                    0xFFA0A8B0
+                } else {
+                    0xFF2838A0 + ((x * 7 + y * 13) % 5) as u32 * 0x00020304
+                }
+            }),
+        );
+
+        textures.add(
+            "checker",
+            Image::generate(64, 64, |x, y| {
+                if (x / 8 + y / 8) % 2 == 0 {
+                    0xFFE0E0E0
+                } else {
+                    0xFF404040
+                }
+            }),
+        );
+
+        textures.add(
+            "planks",
+            Image::generate(64, 64, |x, y| {
+                if x % 16 == 0 {
+                    0xFF103050
+                } else {
+                    0xFF306090 + ((y / 4 + x) % 3) as u32 * 0x00040810
+                }
+            }),
+        );
+
+        // once around the horizon, with the clouds' periods dividing the
+        // width so the seam doesn't show
+        textures.add(
+            SKY_TEXTURE,
+            Image::generate(256, 64, |x, y| {
+                let (u, v) = (x as f32 / 256.0 * TAU, y as f32 / 64.0);
+                let cloud = f32::sin(u * 3.0 + v * 4.0) + f32::sin(u * 7.0 - v * 9.0) * 0.5;
+                let cloud = clamp(cloud - 0.6, 0.0, 1.0) * (1.0 - v);
+                let channel = |a: f32, b: f32| (a + (b - a) * v) * (1.0 - cloud) + 240.0 * cloud;
+                abgr(
+                    channel(40.0, 170.0) as u8,
+                    channel(80.0, 200.0) as u8,
+                    channel(170.0, 235.0) as u8,
+                    0xFF,
+                )
+            }),
+        );
+
+        // transparent around the edge, for drawing as a thing
+        textures.add(
+            "orb",
+            Image::generate(32, 32, |x, y| {
+                let (dx, dy) = (x as i32 * 2 - 31, y as i32 * 2 - 31);
+                let d = dx * dx + dy * dy;
+                if d > 31 * 31 {
+                    0x00000000
+                } else {
+                    // lit from the top left
+                    let glow = 255 - ((dx + 31) * (dx + 31) + (dy + 31) * (dy + 31)) / 16;
+                    let glow = clamp(glow, 64, 255) as u8;
+                    abgr(glow, glow / 2 + 64, 32, 0xFF)
+                }
+            }),
+        );
+
+        textures
+    }
+
+    /// Registers `image` under `name`, replacing the texture already there if
+    /// the name is taken, and returns its id.
+    pub fn add(&mut self, name: &str, image: Image) -> usize {
+        let texture = Texture::new(image);
+        match self.names.get(name) {
+            Some(&id) => {
+                self.arr[id] = texture;
+                id
+            }
+            _ => {
+                self.arr.push(texture);
+                self.names.insert(name.to_string(), self.arr.len() - 1);
+                self.ids.push(name.to_string());
+                self.arr.len() - 1
+            }
+        }
+    }
+
+    /// Loads the image at `path` as texture `name`.
+    pub fn load(&mut self, name: &str, path: &str) -> Result<usize, TextureError> {
+        let image = load_image(path)?;
+        Ok(self.add(name, image))
+    }
+
+    /// Loads every image in `dir`, each named after its file name without
+    /// the extension. Files are loaded in name order so new textures get the
+    /// same ids every time. A file that fails to load is skipped, and the
+    /// rest are loaded anyway. Returns how many were loaded along with why
+    /// the others weren't; only a directory that can't be read is an error.
+    pub fn load_dir(&mut self, dir: &str) -> Result<(usize, Vec<TextureError>), TextureError> {
+        let io_error = |error| TextureError::Io {
+            path: dir.to_string(),
+            error,
+        };
+
+        let mut paths = vec![];
+        for entry in std::fs::read_dir(dir).map_err(io_error)? {
+            let path = entry.map_err(io_error)?.path();
+            let supported = path
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()));
+            if supported && path.is_file() {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+
+        let mut errors = vec![];
+        for path in &paths {
+            let name = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+            if let Err(error) = self.load(name, &path.to_string_lossy()) {
+                errors.push(error);
+            }
+        }
+
+        Ok((paths.len() - errors.len(), errors))
+    }
+
+    /// The id of the texture called `name`.
+    pub fn id(&self, name: &str) -> Option<usize> {
+        self.names.get(name).copied()
+    }
+
+    /// The name of the texture with id `id`, if it is set and exists.
+    pub fn name(&self, id: usize) -> Option<&str> {
+        self.ids
+            .get(id)
+            .filter(|_| id != TEXTURE_NON)
+            .map(|name| name.as_str())
+    }
+
+    /// Every texture name, texture id `i` being `names()[i - 1]`.
+    pub fn names(&self) -> &[String] {
+        &self.ids[1..]
+    }
+
+    /// The texture with id `id`, if it is set and exists.
+    pub fn get(&self, id: usize) -> Option<&Texture> {
+        self.arr.get(id).filter(|_| id != TEXTURE_NON)
+    }
+
+    /// Turns a level's sector, wall and sprite texture references into ids.
+    /// Levels as loaded refer to textures by their 1-based index into
+    /// `level.textures`; afterwards they hold ids in this registry, and
+    /// `level.textures` is this registry's names by id, so references still
+    /// name the right textures when the level is saved. Names that aren't
+    /// registered are returned, and surfaces using them are left untextured.
+    pub fn bind(&self, level: &mut Level) -> Vec<String> {
+        let mut missing = vec![];
+        let ids: Vec<usize> = level
+            .textures
+            .iter()
+            .map(|name| {
+                self.id(name).unwrap_or_else(|| {
+                    missing.push(name.clone());
+                    TEXTURE_NON
+                })
+            })
+            .collect();
+
+        let bind = |r: usize| match r {
+            TEXTURE_NON => TEXTURE_NON,
+            r => ids.get(r - 1).copied().unwrap_or(TEXTURE_NON),
+        };
+        for sector in &mut level.sectors.arr {
+            sector.floor = bind(sector.floor);
+            sector.ceil = bind(sector.ceil);
+        }
+        for wall in &mut level.walls.arr {
+            wall.texture = bind(wall.texture);
+            wall.upper = bind(wall.upper);
+            wall.lower = bind(wall.lower);
+        }
+        for thing in &mut level.things {
+            thing.sprite = bind(thing.sprite);
+        }
+
+        // id `i` is `textures[i - 1]`, as references are before binding
+        level.textures = self.names().to_vec();
+        missing
+    }
+
+    /// Number of textures including the placeholder texture 0.
+    pub fn len(&self) -> usize {
+        self.arr.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.arr.len() <= 1
+    }
+}
+
+impl Default for Textures {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internaltypes::thing::Thing;
+    use crate::res::testing::TempFile;
+    use crate::res::util::kinds::{Sector, Wall};
+
+    fn solid(width: usize, height: usize, px: u32) -> Image {
+        Image::generate(width, height, |_, _| px)
+    }
+
+    #[test]
+    fn names_keep_their_ids() {
+        let mut textures = Textures::new();
+        assert!(textures.is_empty());
+        assert_eq!(textures.len(), 1);
+
+        assert_eq!(textures.add("stone", solid(2, 2, 0xFF808080)), 1);
+        assert_eq!(textures.add("grass", solid(2, 2, 0xFF00FF00)), 2);
+        assert_eq!(textures.add("stone", solid(4, 4, 0xFF404040)), 1);
+
+        assert_eq!(textures.len(), 3);
+        assert_eq!(textures.id("grass"), Some(2));
+        assert_eq!(textures.id("sand"), None);
+        assert_eq!(textures.name(1), Some("stone"));
+        assert_eq!(textures.name(TEXTURE_NON), None);
+        assert_eq!(textures.name(3), None);
+        assert_eq!(textures.names(), ["stone", "grass"]);
+        assert_eq!(textures.get(1).unwrap().width(), 4);
+        assert!(textures.get(TEXTURE_NON).is_none());
+    }
+
+    #[test]
+    fn bind_turns_references_into_ids() {
+        let textures = Textures::builtin();
+        let (bricks, checker) = (textures.id("bricks"), textures.id("checker"));
+
+        let mut level = Level::default();
+        level.sectors.arr.push(Sector {
+            floor: 1,
+            ceil: 3,
+            ..Default::default()
+        });
+        level.walls.arr.push(Wall {
+            texture: 2,
+            upper: 1,
+            lower: TEXTURE_NON,
+            ..Default::default()
+        });
+        let mut orb = Thing::new("orb".to_string());
+        orb.sprite = 4;
+        level.things.push(orb);
+        level.textures = ["checker", "moss", "bricks", "orb"]
+            .map(String::from)
+            .to_vec();
+
+        assert_eq!(textures.bind(&mut level), ["moss"]);
+
+        let sector = &level.sectors.arr[1];
+        assert_eq!((Some(sector.floor), Some(sector.ceil)), (checker, bricks));
+        let wall = &level.walls.arr[0];
+        assert_eq!(wall.texture, TEXTURE_NON);
+        assert_eq!(Some(wall.upper), checker);
+        assert_eq!(wall.lower, TEXTURE_NON);
+        assert_eq!(Some(level.things[0].sprite), textures.id("orb"));
+        assert_eq!(level.textures, textures.names());
+    }
+
+    #[test]
+    fn mips_halve_down_to_one_pixel() {
+        // a 4x2 image, left half black and right half white, with a 3 in
+        // every channel of one pixel
+        let image = Image::generate(4, 2, |x, y| match (x, y) {
+            (0, 0) => 0x03030303,
+            (x, _) if x < 2 => 0x00000000,
+            _ => 0xFFFFFFFF,
+        });
+        let texture = Texture::new(image.clone());
+
+        let sizes: Vec<_> = texture.mips.iter().map(|m| (m.width, m.height)).collect();
+        assert_eq!(sizes, [(4, 2), (2, 1), (1, 1)]);
+        assert_eq!(texture.mips[0], image);
+        // averages round to nearest
+        assert_eq!(texture.mips[1].pixels, [0x01010101, 0xFFFFFFFF]);
+        assert_eq!(texture.mips[2].pixels, [0x80808080]);
+
+        assert_eq!(texture.mip(0.5), &texture.mips[0]);
+        assert_eq!(texture.mip(2.0), &texture.mips[1]);
+        assert_eq!(texture.mip(100.0), &texture.mips[2]);
+    }
+
+    #[test]
+    fn load_dir_skips_bad_files() {
+        let dir = TempFile::new("textures");
+        std::fs::create_dir(dir.path()).unwrap();
+        let write = |name: &str, bytes: &[u8]| {
+            std::fs::write(format!("{}/{}", dir.path(), name), bytes).unwrap()
+        };
+        write("a.ppm", b"P6\n1 1\n255\n\xff\x00\x00");
+        write("b.ppm", b"P6\n2 2\n255\n\x00");
+        write("c.txt", b"not an image");
+        write("d.PPM", b"P3\n1 1\n255\n0 0 255\n");
+
+        let mut textures = Textures::new();
+        let (loaded, errors) = textures.load_dir(dir.path()).unwrap();
+
+        assert_eq!(loaded, 2);
+        assert_eq!(errors.len(), 1);
+        assert!(
+            matches!(&errors[0], TextureError::Corrupt { path, .. } if path.ends_with("b.ppm"))
+        );
+        assert_eq!(textures.names(), ["a", "d"]);
+        assert_eq!(textures.get(1).unwrap().mips[0].pixels, [0xFF0000FF]);
+        assert_eq!(textures.get(2).unwrap().mips[0].pixels, [0xFFFF0000]);
+    }
+
+    #[test]
+    fn load_dir_needs_the_directory() {
+        let dir = TempFile::new("no-textures");
+        assert!(matches!(
+            Textures::new().load_dir(dir.path()),
+            Err(TextureError::Io { .. })
+        ));
+    }
+}
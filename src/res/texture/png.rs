@@ -0,0 +1,270 @@
+use super::inflate::inflate_zlib;
+use super::{abgr, image_bytes, Image};
+
+const SIGNATURE: &[u8; 8] = b"\x89PNG\r\n\x1a\n";
+
+fn be32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// Decodes a non-interlaced PNG with 8 or 16 bits per channel, or a palette.
+pub fn decode_png(bytes: &[u8]) -> Result<Image, String> {
+    if !bytes.starts_with(SIGNATURE) {
+        return Err("not a PNG file".to_string());
+    }
+
+    let mut header = None;
+    let mut palette: Vec<u32> = vec![];
+    let mut idat = vec![];
+
+    let mut at = SIGNATURE.len();
+    loop {
+        let chunk = bytes.get(at..at + 8).ok_or("file ends before IEND")?;
+        let len = be32(chunk) as usize;
+        let kind = &chunk[4..8];
+        let data = (at + 8)
+            .checked_add(len)
+            .and_then(|end| bytes.get(at + 8..end))
+            .ok_or("chunk runs past the end of the file")?;
+        at += 12 + len;
+
+        match kind {
+            b"IHDR" if data.len() == 13 => header = Some(data),
+            b"PLTE" => {
+                palette = data
+                    .chunks_exact(3)
+                    .map(|c| abgr(c[0], c[1], c[2], 0xFF))
+                    .collect()
+            }
+            b"tRNS" => {
+                for (entry, &alpha) in palette.iter_mut().zip(data) {
+                    *entry = (*entry & 0x00FFFFFF) | (alpha as u32) << 24;
+                }
+            }
+            b"IDAT" => idat.extend_from_slice(data),
+            b"IEND" => break,
+            _ => {}
+        }
+    }
+
+    let header = header.ok_or("missing IHDR")?;
+    let width = be32(&header[0..4]) as usize;
+    let height = be32(&header[4..8]) as usize;
+    let (depth, color, interlace) = (header[8], header[9], header[12]);
+
+    if interlace != 0 {
+        return Err("interlaced PNGs are not supported".to_string());
+    }
+
+    let channels = match color {
+        0 => 1,
+        2 => 3,
+        3 => 1,
+        4 => 2,
+        6 => 4,
+        _ => return Err(format!("bad PNG colour type {}", color)),
+    };
+    let ok_depth = match color {
+        3 => depth == 8,
+        _ => depth == 8 || depth == 16,
+    };
+    if !ok_depth {
+        return Err(format!(
+            "{}-bit PNGs of colour type {} are not supported",
+            depth, color
+        ));
+    }
+
+    // every scanline starts with its filter type
+    let bpp = channels * depth as usize / 8;
+    let size = image_bytes(width, height, bpp, 0)?;
+    let raw_size = image_bytes(width, height, bpp, 1)?;
+    let stride = width * bpp;
+    let raw = inflate_zlib(&idat)?;
+    if raw.len() < raw_size {
+        return Err("image data is too short".to_string());
+    }
+
+    // undo the per-scanline filters
+    let mut data = vec![0u8; size];
+    for y in 0..height {
+        let filter = raw[y * (stride + 1)];
+        let src = &raw[y * (stride + 1) + 1..][..stride];
+        let (above, row) = data.split_at_mut(y * stride);
+        let prev = above
+            .get(above.len().saturating_sub(stride)..)
+            .filter(|_| y > 0);
+        let row = &mut row[..stride];
+
+        for i in 0..stride {
+            let a = if i >= bpp { row[i - bpp] } else { 0 };
+            let b = prev.map_or(0, |prev| prev[i]);
+            let c = if i >= bpp {
+                prev.map_or(0, |prev| prev[i - bpp])
+            } else {
+                0
+            };
+
+            row[i] = src[i].wrapping_add(match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => paeth(a, b, c),
+                _ => return Err(format!("bad PNG filter type {}", filter)),
+            });
+        }
+    }
+
+    // 16-bit samples keep their high byte
+    let step = depth as usize / 8;
+    let sample = |px: &[u8], channel: usize| px[channel * step];
+
+    let pixels = data
+        .chunks_exact(bpp)
+        .map(|px| match color {
+            0 => abgr(sample(px, 0), sample(px, 0), sample(px, 0), 0xFF),
+            2 => abgr(sample(px, 0), sample(px, 1), sample(px, 2), 0xFF),
+            3 => palette.get(px[0] as usize).copied().unwrap_or(0xFF000000),
+            4 => abgr(sample(px, 0), sample(px, 0), sample(px, 0), sample(px, 1)),
+            _ => abgr(sample(px, 0), sample(px, 1), sample(px, 2), sample(px, 3)),
+        })
+        .collect();
+
+    Ok(Image {
+        width,
+        height,
+        pixels,
+    })
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        // the decoder doesn't check CRCs
+        [&(data.len() as u32).to_be_bytes(), &kind[..], data, &[0; 4]].concat()
+    }
+
+    /// A PNG of `raw` scanlines, stored uncompressed, with `extra` chunks
+    /// before the image data.
+    fn png(
+        width: u32,
+        height: u32,
+        depth: u8,
+        color: u8,
+        extra: &[Vec<u8>],
+        raw: &[u8],
+    ) -> Vec<u8> {
+        let ihdr = [
+            &width.to_be_bytes()[..],
+            &height.to_be_bytes(),
+            &[depth, color, 0, 0, 0],
+        ]
+        .concat();
+        let len = raw.len() as u16;
+        let zlib = [
+            &[0x78, 0x01, 1][..],
+            &len.to_le_bytes(),
+            &(!len).to_le_bytes(),
+            raw,
+            &[0; 4],
+        ]
+        .concat();
+
+        [
+            SIGNATURE.to_vec(),
+            chunk(b"IHDR", &ihdr),
+            extra.concat(),
+            chunk(b"IDAT", &zlib),
+            chunk(b"IEND", &[]),
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn rgb_with_filters() {
+        // the first row unfiltered, the second adding the pixel to the left
+        let raw = [0, 10, 20, 30, 40, 50, 60, 1, 1, 2, 3, 1, 1, 1];
+        let image = decode_png(&png(2, 2, 8, 2, &[], &raw)).unwrap();
+        assert_eq!(
+            image.pixels,
+            vec![
+                abgr(10, 20, 30, 0xFF),
+                abgr(40, 50, 60, 0xFF),
+                abgr(1, 2, 3, 0xFF),
+                abgr(2, 3, 4, 0xFF),
+            ]
+        );
+
+        // the second row adding the pixel above instead
+        let raw = [0, 10, 20, 30, 40, 50, 60, 2, 1, 2, 3, 1, 1, 1];
+        let image = decode_png(&png(2, 2, 8, 2, &[], &raw)).unwrap();
+        assert_eq!(
+            image.pixels[2..],
+            [abgr(11, 22, 33, 0xFF), abgr(41, 51, 61, 0xFF)]
+        );
+    }
+
+    #[test]
+    fn palette_and_transparency() {
+        let extra = [
+            chunk(b"PLTE", &[255, 0, 0, 0, 0, 255]),
+            chunk(b"tRNS", &[0]),
+        ];
+        let image = decode_png(&png(3, 1, 8, 3, &extra, &[0, 0, 1, 7])).unwrap();
+        assert_eq!(
+            image.pixels,
+            vec![abgr(255, 0, 0, 0), abgr(0, 0, 255, 0xFF), 0xFF000000]
+        );
+    }
+
+    #[test]
+    fn sixteen_bit_gray_alpha() {
+        let image = decode_png(&png(1, 1, 16, 4, &[], &[0, 0x80, 0x01, 0x40, 0x02])).unwrap();
+        assert_eq!(image.pixels, vec![abgr(0x80, 0x80, 0x80, 0x40)]);
+    }
+
+    #[test]
+    fn bad_files() {
+        let err = |bytes: &[u8]| decode_png(bytes).unwrap_err();
+
+        assert_eq!(err(b"GIF89a"), "not a PNG file");
+        assert_eq!(
+            err(&png(0x4000_0000, 0x4000_0000, 16, 6, &[], &[])),
+            "1073741824x1073741824 image is larger than 16384x16384"
+        );
+        assert_eq!(
+            err(&png(2, 2, 8, 2, &[], &[0; 7])),
+            "image data is too short"
+        );
+        assert_eq!(err(&png(1, 1, 8, 0, &[], &[5, 0])), "bad PNG filter type 5");
+        assert_eq!(err(&png(1, 1, 8, 5, &[], &[0, 0])), "bad PNG colour type 5");
+        assert_eq!(
+            err(&png(1, 1, 4, 0, &[], &[0, 0])),
+            "4-bit PNGs of colour type 0 are not supported"
+        );
+
+        let mut truncated = png(1, 1, 8, 0, &[], &[0, 0]);
+        truncated[SIGNATURE.len()..][..4].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert_eq!(err(&truncated), "chunk runs past the end of the file");
+        assert_eq!(err(&SIGNATURE[..]), "file ends before IEND");
+    }
+}
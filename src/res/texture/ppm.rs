@@ -0,0 +1,132 @@
+use super::{abgr, image_bytes, Image};
+
+/// Decodes a binary (P6) or plain (P3) PPM.
+pub fn decode_ppm(bytes: &[u8]) -> Result<Image, String> {
+    let binary = match bytes.get(..2) {
+        Some(b"P6") => true,
+        Some(b"P3") => false,
+        _ => return Err("not a PPM file".to_string()),
+    };
+
+    // header fields are separated by whitespace and may have comments
+    // between them, the binary pixel data follows a single whitespace byte
+    let mut at = 2;
+    let mut field = || -> Result<u32, String> {
+        loop {
+            match bytes.get(at) {
+                Some(b'#') => {
+                    while bytes.get(at).is_some_and(|&c| c != b'\n') {
+                        at += 1;
+                    }
+                }
+                Some(c) if c.is_ascii_whitespace() => at += 1,
+                Some(_) => break,
+                _ => return Err("header ends early".to_string()),
+            }
+        }
+        let start = at;
+        while bytes.get(at).is_some_and(|c| c.is_ascii_digit()) {
+            at += 1;
+        }
+        std::str::from_utf8(&bytes[start..at])
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| "bad header field".to_string())
+    };
+
+    let width = field()? as usize;
+    let height = field()? as usize;
+    let maxval = field()?;
+    if maxval == 0 || maxval > 255 {
+        return Err(format!("maxval {} is not supported", maxval));
+    }
+
+    let size = image_bytes(width, height, 3, 0)?;
+    let too_large = |sample: u32| format!("sample {} is larger than maxval {}", sample, maxval);
+
+    let samples: Vec<u8> = if binary {
+        let start = at + 1;
+        let samples = bytes
+            .get(start..start + size)
+            .ok_or("pixel data is too short")?;
+        if let Some(&sample) = samples.iter().find(|&&s| s as u32 > maxval) {
+            return Err(too_large(sample as u32));
+        }
+        samples.to_vec()
+    } else {
+        // every sample takes at least a byte, however large the header says
+        // the image is
+        let mut samples = Vec::with_capacity(usize::min(size, bytes.len()));
+        for _ in 0..size {
+            let sample = field()?;
+            if sample > maxval {
+                return Err(too_large(sample));
+            }
+            samples.push(sample as u8);
+        }
+        samples
+    };
+
+    let scale = |s: u8| (s as u32 * 255 / maxval) as u8;
+    let pixels = samples
+        .chunks_exact(3)
+        .map(|c| abgr(scale(c[0]), scale(c[1]), scale(c[2]), 0xFF))
+        .collect();
+
+    Ok(Image {
+        width,
+        height,
+        pixels,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn binary() {
+        let mut bytes = b"P6\n# a comment\n2 1\n255\n".to_vec();
+        bytes.extend([255, 0, 0, 0, 128, 255]);
+        let image = decode_ppm(&bytes).unwrap();
+        assert_eq!((image.width, image.height), (2, 1));
+        assert_eq!(
+            image.pixels,
+            vec![abgr(255, 0, 0, 0xFF), abgr(0, 128, 255, 0xFF)]
+        );
+    }
+
+    #[test]
+    fn plain_scaled() {
+        let image = decode_ppm(b"P3 1 2 15\n15 0 0\n 0 5 15").unwrap();
+        assert_eq!(
+            image.pixels,
+            vec![abgr(255, 0, 0, 0xFF), abgr(0, 85, 255, 0xFF)]
+        );
+    }
+
+    #[test]
+    fn bad_files() {
+        let err = |bytes: &[u8]| decode_ppm(bytes).unwrap_err();
+
+        assert_eq!(err(b"P5 1 1 255 0"), "not a PPM file");
+        assert_eq!(err(b"P3 1 1"), "header ends early");
+        assert_eq!(err(b"P3 1 x 255"), "bad header field");
+        assert_eq!(err(b"P3 1 1 65535 0 0 0"), "maxval 65535 is not supported");
+        assert_eq!(err(b"P6 2 2 255\n\0\0\0"), "pixel data is too short");
+        assert_eq!(
+            err(b"P3 1 1 255 0 300 0"),
+            "sample 300 is larger than maxval 255"
+        );
+        assert_eq!(
+            err(b"P6 1 1 15\n\0\x10\0"),
+            "sample 16 is larger than maxval 15"
+        );
+        assert_eq!(
+            err(b"P3 4294967295 4294967295 255 0"),
+            "4294967295x4294967295 image is larger than 16384x16384"
+        );
+        // well within the limit, but far more pixels than there is data
+        assert_eq!(err(b"P3 16384 16384 255 1 2 3"), "header ends early");
+    }
+}
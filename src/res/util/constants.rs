@@ -2,22 +2,26 @@
 //use lazy_static::lazy_static;
 use std::f32::consts::PI;
 
-
 pub const TAU: f32 = 2.0 * PI;
 
 pub const PI_2: f32 = PI / 2.0;
 
 pub const PI_4: f32 = PI / 4.0;
 
+// defaults for `RenderConfig`, which the renderer actually goes by
 pub const SCREEN_WIDTH: usize = 384;
 
-
 pub const SCREEN_HEIGHT: i32 = 216;
 
 pub const EYE_Z: f32 = 1.65;
 
-pub const HFOV: f32 = std::f32::consts::FRAC_PI_2;
+// the highest step up the camera climbs, and the least room it needs between
+// the eye and the ceiling
+pub const STEP_HEIGHT: f32 = 1.0;
 
+pub const HEADROOM: f32 = 0.2;
+
+pub const HFOV: f32 = std::f32::consts::FRAC_PI_2;
 
 pub const VFOV: f32 = 0.5;
 
@@ -27,7 +31,29 @@ pub const ZFAR: f32 = 128.0;
 
 pub const SECTOR_NON: i32 = 0;
 
-pub const SECTOR_MAX: i32 = 128;
+pub const TEXTURE_NON: usize = 0;
+
+// a ceiling with this texture name opens onto the sky, which is drawn from
+// the texture registered under it
+pub const SKY_TEXTURE: &str = "sky";
+
+// a wall with this texture name is a mirror
+pub const MIRROR_TEXTURE: &str = "mirror";
+
+// how many mirrors in a row the renderer looks through, the last drawn flat
+// in `MIRROR_TINT`
+pub const MIRROR_DEPTH_MAX: usize = 6;
+
+// everything seen in a mirror is blended this far towards `MIRROR_TINT`, out
+// of 256, and again for every further mirror
+pub const MIRROR_TINT: u32 = 0xFFB0A090;
+pub const MIRROR_TINT_AMOUNT: u32 = 40;
+
+pub const LIGHT_FULL: f32 = 1.0;
 
-pub const QUEUE_MAX: i32 = 64;
+// light fades with depth as 1 / (1 + depth * LIGHT_FALLOFF)
+pub const LIGHT_FALLOFF: f32 = 0.08;
 
+// how many transforming portals in a row the renderer looks through, so two
+// that see each other don't go on forever; the last is drawn as a wall
+pub const PORTAL_DEPTH_MAX: usize = 8;
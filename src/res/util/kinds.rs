@@ -1,55 +1,289 @@
-#[derive(Debug, Clone)]
+use super::constants::{HFOV, SCREEN_HEIGHT, SCREEN_WIDTH, VFOV, ZFAR, ZNEAR};
+
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct V2 {
     pub x: f32,
     pub y: f32,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
 pub struct V2i {
     pub x: i32,
     pub y: i32,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct Wall {
     pub a: V2i,
     pub b: V2i,
-    pub portal: usize
+    pub portal: usize,
+    /// texture ids, 0 for none: the wall itself, then the steps up to and
+    /// down from the sector behind a portal
+    pub texture: usize,
+    pub upper: usize,
+    pub lower: usize,
+    /// for a portal into a sector somewhere else in the map, how to get
+    /// there
+    pub transform: Option<PortalTransform>,
+    /// a solid wall that reflects its sector instead of being drawn
+    pub mirror: bool,
 }
 
-#[derive(Debug, Clone)]
+/// Carries what passes through a portal into the sector behind it, which can
+/// be anywhere in the map: points are turned by `angle` radians about the
+/// origin, then moved by `offset`. The wall leading back should undo it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PortalTransform {
+    pub offset: V2,
+    pub angle: f32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Walls {
-    pub arr: [Wall; 128],
-    pub n: usize,
+    pub arr: Vec<Wall>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct Sector {
     pub id: i32,
     pub firstwall: usize,
     pub nwalls: usize,
     pub zfloor: f32,
     pub zceil: f32,
+    /// texture ids of the floor and ceiling, 0 for none
+    pub floor: usize,
+    pub ceil: usize,
+    /// 0 for dark through `LIGHT_FULL`
+    pub light: f32,
+    /// open to the sky instead of having a ceiling
+    pub sky: bool,
+    /// tilts of the floor and ceiling, which are flat at `zfloor` and
+    /// `zceil` without one
+    pub floor_slope: Option<Slope>,
+    pub ceil_slope: Option<Slope>,
 }
 
-#[derive(Debug, Clone)]
+/// Tilts a floor or ceiling about one of its sector's walls: along wall
+/// `wall`, counted from the sector's `firstwall`, it stays at the sector's
+/// height, and it rises by `delta` for every unit of distance into the
+/// sector, or falls for a negative `delta`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Slope {
+    pub wall: usize,
+    pub delta: f32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Sectors {
-    pub arr: [Sector; 32],
-    pub n: usize,
+    pub arr: Vec<Sector>,
 }
 
 #[derive(Debug, Clone)]
 pub struct Camera {
     pub pos: V2,
+    /// height of the eye, `EYE_Z` above the floor under `pos`
+    pub z: f32,
     pub angle: f32,
     pub anglecos: f32,
     pub anglesin: f32,
     pub sector: i32,
+    /// how many mirrors the view is seen through, 0 for the player's own
+    pub reflections: usize,
+}
+
+/// Fades whatever is further than `start` towards `color`, thickening by
+/// `density` per world unit. Anything at the far clip is lost in the fog
+/// entirely, so nothing pops out of it there.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fog {
+    pub color: u32,
+    pub start: f32,
+    pub density: f32,
+}
+
+/// What the renderer draws: a `width` x `height` frame showing `hfov`
+/// radians across, with a unit of height at depth 1 spanning `vfov` of the
+/// frame's height, and nothing nearer than `znear` or further than `zfar`.
+/// With more than one of `threads` the frame is split into that many strips
+/// of columns drawn in parallel, which gives the same frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RenderConfig {
+    pub width: usize,
+    pub height: usize,
+    pub hfov: f32,
+    pub vfov: f32,
+    pub znear: f32,
+    pub zfar: f32,
+    pub threads: usize,
+}
+
+impl RenderConfig {
+    /// A `width` x `height` frame with the default field of view and clip
+    /// planes, drawn on one thread.
+    pub const fn new(width: usize, height: usize) -> Self {
+        RenderConfig {
+            width,
+            height,
+            hfov: HFOV,
+            vfov: VFOV,
+            znear: ZNEAR,
+            zfar: ZFAR,
+            threads: 1,
+        }
+    }
+}
+
+impl Default for RenderConfig {
+    fn default() -> Self {
+        Self::new(SCREEN_WIDTH, SCREEN_HEIGHT as usize)
+    }
+}
+
+impl Walls {
+    pub fn new() -> Self {
+        Walls { arr: vec![] }
+    }
+
+    pub fn len(&self) -> usize {
+        self.arr.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.arr.is_empty()
+    }
+}
+
+impl Default for Walls {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PortalTransform {
+    /// Where `p` ends up on the other side.
+    pub fn apply(&self, p: &V2) -> V2 {
+        let (sin, cos) = self.angle.sin_cos();
+        V2::new(
+            p.x * cos - p.y * sin + self.offset.x,
+            p.x * sin + p.y * cos + self.offset.y,
+        )
+    }
+
+    /// `camera` carried through to the other side, still in its sector.
+    pub fn apply_camera(&self, camera: &Camera) -> Camera {
+        let angle = camera.angle + self.angle;
+        Camera {
+            pos: self.apply(&camera.pos),
+            angle,
+            anglecos: angle.cos(),
+            anglesin: angle.sin(),
+            ..camera.clone()
+        }
+    }
+}
+
+impl Camera {
+    /// Whether the view is flipped left to right, having been seen in an odd
+    /// number of mirrors.
+    pub fn mirrored(&self) -> bool {
+        self.reflections % 2 == 1
+    }
+
+    /// The camera reflected in a mirror along the line through `a` and `b`,
+    /// still in its sector. What it sees is flipped left to right.
+    pub fn reflect(&self, a: &V2, b: &V2) -> Camera {
+        let (dx, dy) = (b.x - a.x, b.y - a.y);
+        let length = f32::sqrt(dx * dx + dy * dy);
+        let (dx, dy) = (dx / length, dy / length);
+
+        // the position's offset from `a`, with the part across the line
+        // turned around
+        let (px, py) = (self.pos.x - a.x, self.pos.y - a.y);
+        let along = px * dx + py * dy;
+        let angle = 2.0 * f32::atan2(dy, dx) - self.angle;
+        Camera {
+            pos: V2::new(a.x + 2.0 * along * dx - px, a.y + 2.0 * along * dy - py),
+            angle,
+            anglecos: angle.cos(),
+            anglesin: angle.sin(),
+            reflections: self.reflections + 1,
+            ..self.clone()
+        }
+    }
+}
+
+impl Sector {
+    /// Height of the floor at `p`.
+    pub fn floor_at(&self, walls: &Walls, p: &V2) -> f32 {
+        self.height_at(self.zfloor, &self.floor_slope, walls, p)
+    }
+
+    /// Height of the ceiling at `p`.
+    pub fn ceil_at(&self, walls: &Walls, p: &V2) -> f32 {
+        self.height_at(self.zceil, &self.ceil_slope, walls, p)
+    }
+
+    /// How much the floor rises per unit moved along x and y.
+    pub fn floor_gradient(&self, walls: &Walls) -> V2 {
+        self.floor_slope
+            .as_ref()
+            .map_or(V2::new(0.0, 0.0), |slope| self.gradient(slope, walls))
+    }
+
+    /// How much the ceiling rises per unit moved along x and y.
+    pub fn ceil_gradient(&self, walls: &Walls) -> V2 {
+        self.ceil_slope
+            .as_ref()
+            .map_or(V2::new(0.0, 0.0), |slope| self.gradient(slope, walls))
+    }
+
+    fn height_at(&self, z: f32, slope: &Option<Slope>, walls: &Walls, p: &V2) -> f32 {
+        let Some(slope) = slope else {
+            return z;
+        };
+        let a = walls.arr[self.firstwall + slope.wall].a.clone().v2i_to_v2();
+        let g = self.gradient(slope, walls);
+        z + g.x * (p.x - a.x) + g.y * (p.y - a.y)
+    }
+
+    fn gradient(&self, slope: &Slope, walls: &Walls) -> V2 {
+        let wall = &walls.arr[self.firstwall + slope.wall];
+        let (dx, dy) = ((wall.b.x - wall.a.x) as f32, (wall.b.y - wall.a.y) as f32);
+        let length = f32::sqrt(dx * dx + dy * dy);
+
+        // walls wind clockwise, so into the sector is to the right of a wall
+        V2::new(dy * slope.delta / length, -dx * slope.delta / length)
+    }
+}
+
+impl Sectors {
+    /// Starts out holding only the placeholder for sector 0, which does not
+    /// exist, so sector ids can be used as indices directly.
+    pub fn new() -> Self {
+        Sectors {
+            arr: vec![Sector::default()],
+        }
+    }
+
+    /// Number of sectors including the placeholder sector 0.
+    pub fn len(&self) -> usize {
+        self.arr.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.arr.len() <= 1
+    }
+}
+
+impl Default for Sectors {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl V2 {
     pub fn new(x: f32, y: f32) -> Self {
-        V2 {x, y}
+        V2 { x, y }
     }
 
     pub fn v2_to_v2i(self) -> V2i {
@@ -58,11 +292,11 @@ impl V2 {
 }
 
 impl V2i {
-    pub fn new(x: i32, y:i32) -> Self {
-        V2i {x, y}
+    pub fn new(x: i32, y: i32) -> Self {
+        V2i { x, y }
     }
 
     pub fn v2i_to_v2(self) -> V2 {
         V2::new(self.x as f32, self.y as f32)
     }
-}
\ No newline at end of file
+}
@@ -1,5 +1,5 @@
-use super::constants::{HFOV, PI_2, PI_4, SCREEN_WIDTH, TAU};
-use crate::res::{game::State, util::kinds::V2};
+use super::constants::TAU;
+use crate::res::util::kinds::{Camera, RenderConfig, V2};
 use std::f32::consts::PI;
 
 #[inline]
@@ -58,6 +58,11 @@ pub fn clamp<T: PartialOrd>(x: T, min_val: T, max_val: T) -> T {
     }
 }
 
+#[inline]
+pub fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
 #[inline]
 pub fn ifnan(x: f32, alt: f32) -> f32 {
     if x.is_nan() {
@@ -116,10 +121,29 @@ pub fn abgr_mul(col: u32, a: u32) -> u32 {
     0xFF000000 | (br & 0xFF00FF) | (g & 0x00FF00)
 }
 
+/// Blends `col` towards `other` by `t` out of 256.
+#[inline]
+pub fn abgr_mix(col: u32, other: u32, t: u32) -> u32 {
+    let br: u32 = ((col & 0xFF00FF) * (256 - t) + (other & 0xFF00FF) * t) >> 8;
+    let g: u32 = ((col & 0x00FF00) * (256 - t) + (other & 0x00FF00) * t) >> 8;
+
+    0xFF000000 | (br & 0xFF00FF) | (g & 0x00FF00)
+}
+
+/// The screen column a view angle projects to, the edges of `config.hfov`
+/// landing on the edges of the screen.
 #[inline]
-pub fn screen_angle_to_x(angle: f32) -> i32 {
-    ((SCREEN_WIDTH / 2) as i32)
-        * (1.0 - f32::tan(((angle + (HFOV / 2.0)) / HFOV) * PI_2 - PI_4)) as i32
+pub fn screen_angle_to_x(angle: f32, config: &RenderConfig) -> i32 {
+    let half = (config.width / 2) as f32;
+    (half * (1.0 - f32::tan(angle) / f32::tan(config.hfov / 2.0))) as i32
+}
+
+/// The inverse of `screen_angle_to_x`: the view angle at screen position `x`,
+/// which may fall between columns.
+#[inline]
+pub fn screen_x_to_angle(x: f32, config: &RenderConfig) -> f32 {
+    let half = (config.width / 2) as f32;
+    f32::atan((1.0 - x / half) * f32::tan(config.hfov / 2.0))
 }
 
 #[inline]
@@ -127,12 +151,28 @@ pub fn normalize_angle(a: f32) -> f32 {
     a - (TAU * f32::floor((a + PI) / TAU))
 }
 
+/// `p` relative to the camera, +y ahead and +x to the right, or to the left
+/// for a mirrored camera.
 #[inline]
-pub fn world_pos_to_camera(p: V2, state: State) -> V2 {
-    let u: V2 = V2::new(p.x - state.camera.pos.x, p.y - state.camera.pos.y);
+pub fn world_pos_to_camera(p: V2, camera: &Camera) -> V2 {
+    let u: V2 = V2::new(p.x - camera.pos.x, p.y - camera.pos.y);
+    let x = u.x * camera.anglesin - u.y * camera.anglecos;
     V2::new(
-        u.x * state.camera.anglesin - u.y * state.camera.anglecos,
-        u.x * state.camera.anglecos + u.y * state.camera.anglesin,
+        if camera.mirrored() { -x } else { x },
+        u.x * camera.anglecos + u.y * camera.anglesin,
     )
 }
 
+/// The inverse of `world_pos_to_camera`.
+#[inline]
+pub fn camera_pos_to_world(p: V2, camera: &Camera) -> V2 {
+    let p = if camera.mirrored() {
+        V2::new(-p.x, p.y)
+    } else {
+        p
+    };
+    V2::new(
+        p.x * camera.anglesin + p.y * camera.anglecos + camera.pos.x,
+        p.y * camera.anglesin - p.x * camera.anglecos + camera.pos.y,
+    )
+}
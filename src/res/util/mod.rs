@@ -2,4 +2,4 @@ pub mod constants;
 
 pub mod math;
 
-pub mod kinds;
\ No newline at end of file
+pub mod kinds;